@@ -0,0 +1,46 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use froggy::{Pointer, Storage};
+use std::thread;
+
+/// Worker threads sharing the pointers.
+const N_THREADS: usize = 4;
+
+/// Clone/drop pairs done by each thread.
+const N_CLONES: usize = 50_000;
+
+fn churn(pointer: &Pointer<u32>, count: usize) {
+    for _ in 0..count {
+        drop(pointer.clone());
+    }
+}
+
+fn bench_churn_single(c: &mut Criterion) {
+    let mut storage = Storage::new();
+    let pointer = storage.create(0u32);
+
+    c.bench_function("pointer-churn-single", move |b| {
+        b.iter(|| {
+            churn(&pointer, N_THREADS * N_CLONES);
+            storage.sync_pending();
+        })
+    });
+}
+
+fn bench_churn_threads(c: &mut Criterion) {
+    let mut storage = Storage::new();
+    let pointer = storage.create(0u32);
+
+    c.bench_function("pointer-churn-threads", move |b| {
+        b.iter(|| {
+            thread::scope(|scope| {
+                for _ in 0..N_THREADS {
+                    scope.spawn(|| churn(&pointer, N_CLONES));
+                }
+            });
+            storage.sync_pending();
+        })
+    });
+}
+
+criterion_group!(benches, bench_churn_single, bench_churn_threads);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use froggy::{Pointer, Storage};
+
+/// Components in the storage.
+const N_COMPONENTS: usize = 100_000;
+
+/// Lookups done by each iteration.
+const N_LOOKUPS: usize = 1_000_000;
+
+fn build() -> (Storage<u32>, Vec<Pointer<u32>>) {
+    let mut storage = Storage::with_capacity(N_COMPONENTS);
+    let pointers = (0..N_COMPONENTS as u32)
+        .map(|i| storage.create(i))
+        .collect();
+    (storage, pointers)
+}
+
+fn bench_index(c: &mut Criterion) {
+    let (storage, pointers) = build();
+
+    c.bench_function("pointer-lookup-index", move |b| {
+        b.iter(|| {
+            let mut sum = 0u32;
+            for pointer in pointers.iter().cycle().take(N_LOOKUPS) {
+                sum = sum.wrapping_add(storage[pointer]);
+            }
+            sum
+        })
+    });
+}
+
+fn bench_index_mut(c: &mut Criterion) {
+    let (mut storage, pointers) = build();
+
+    c.bench_function("pointer-lookup-index-mut", move |b| {
+        b.iter(|| {
+            for pointer in pointers.iter().cycle().take(N_LOOKUPS) {
+                storage[pointer] += 1;
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_index, bench_index_mut);
+criterion_main!(benches);
@@ -243,6 +243,8 @@ fn main() {
     // Thus, all will be lost if we lock for writing now, but locking for reading retains the
     // contents, and cube creation will add references to them, so they will stay alive.
     let mut cubes = create_cubes(&mut node_store, &material_store, &level_store);
+    // the world space pass needs the parents to be iterated before their children
+    node_store.sort_by_dependency(|node| node.parent.iter().collect());
     println!(
         "Initialized {} cubes on {} levels",
         cubes.len(),
@@ -339,6 +341,9 @@ fn main() {
         }
 
         // re-compute world spaces, using streaming iteration
+        debug_assert!(node_store
+            .dependency_violations(|node| node.parent.iter().collect())
+            .is_empty());
         {
             let mut cursor = node_store.cursor();
             while let Some((left, mut item, _)) = cursor.next() {
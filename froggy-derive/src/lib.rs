@@ -0,0 +1,159 @@
+/*!
+Derive macros for [froggy](https://docs.rs/froggy).
+
+`#[derive(PointerVisit)]` implements `froggy::PointerVisit` by visiting every field
+of a struct, or of the active enum variant, in declaration order.
+The fields that don't implement `PointerVisit`, and can't contain any pointers,
+are skipped with `#[pointer_visit(skip)]`.
+The impl of a generic type requires the visited field types that use its type parameters
+to implement `PointerVisit`, so a parameter only used as in `Pointer<T>` is not bounded.
+
+```rust
+use froggy::{Pointer, PointerVisit, WeakPointer};
+
+#[derive(PointerVisit)]
+struct Node {
+    #[pointer_visit(skip)]
+    name: std::path::PathBuf,
+    parent: Option<Pointer<Node>>,
+    children: Vec<WeakPointer<Node>>,
+}
+```
+*/
+#![warn(missing_docs)]
+
+extern crate proc_macro;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Ident, Result, Type};
+
+/// Derive `froggy::PointerVisit`, see the crate documentation.
+#[proc_macro_derive(PointerVisit, attributes(pointer_visit))]
+pub fn derive_pointer_visit(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let mut field_types = Vec::new();
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let (pattern, visits) = destructure(&data.fields, &mut field_types)?;
+            quote! {
+                let #name #pattern = *self;
+                #(#visits)*
+            }
+        }
+        Data::Enum(ref data) => {
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let variant_name = &variant.ident;
+                let (pattern, visits) = destructure(&variant.fields, &mut field_types)?;
+                arms.push(quote! {
+                    #name::#variant_name #pattern => { #(#visits)* }
+                });
+            }
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(ref data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "PointerVisit can't be derived for unions",
+            ));
+        }
+    };
+
+    // Only the visited field types that depend on the type parameters are bounded,
+    // since a parameter may only appear inside of a pointer, like in `Pointer<T>`.
+    let params: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let mut bounded = Vec::new();
+    for ty in field_types {
+        let key = ty.to_token_stream().to_string();
+        if mentions(ty.to_token_stream(), &params) && !bounded.contains(&key) {
+            bounded.push(key);
+            input
+                .generics
+                .make_where_clause()
+                .predicates
+                .push(parse_quote!(#ty: ::froggy::PointerVisit));
+        }
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::froggy::PointerVisit for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn visit_pointers<__V: ::froggy::PointerVisitor>(&self, visitor: &mut __V) {
+                #body
+            }
+        }
+    })
+}
+
+/// Return the pattern binding the fields by reference,
+/// and the statements visiting the ones that are not skipped,
+/// whose types are added to `types`.
+fn destructure(fields: &Fields, types: &mut Vec<Type>) -> Result<(TokenStream, Vec<TokenStream>)> {
+    let mut bindings = Vec::new();
+    let mut visits = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        let binding = format_ident!("__field{}", index);
+        if !is_skipped(field)? {
+            types.push(field.ty.clone());
+            visits.push(quote! {
+                ::froggy::PointerVisit::visit_pointers(#binding, visitor);
+            });
+        }
+        bindings.push(binding);
+    }
+    let pattern = match *fields {
+        Fields::Named(_) => {
+            let names: Vec<&Ident> = fields.iter().filter_map(|f| f.ident.as_ref()).collect();
+            quote!({ #(#names: ref #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(( #(ref #bindings),* )),
+        Fields::Unit => quote!(),
+    };
+    Ok((pattern, visits))
+}
+
+/// Check if any of the identifiers is used in the tokens.
+fn mentions(tokens: TokenStream, idents: &[Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ref ident) => idents.contains(ident),
+        TokenTree::Group(group) => mentions(group.stream(), idents),
+        _ => false,
+    })
+}
+
+fn is_skipped(field: &syn::Field) -> Result<bool> {
+    let mut skip = false;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("pointer_visit") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown pointer_visit attribute"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
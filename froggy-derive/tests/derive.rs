@@ -0,0 +1,177 @@
+use froggy::{Pointer, PointerVisit, PointerVisitor, Storage, WeakPointer};
+
+/// Stand-in for the `cgmath` transform of the cubes demo.
+#[derive(Clone, Copy, Default)]
+struct Space {
+    _disp: [f32; 3],
+    _scale: f32,
+}
+
+struct Level {
+    _speed: f32,
+}
+
+struct Material {
+    _color: [f32; 4],
+}
+
+#[derive(PointerVisit)]
+struct Node {
+    #[pointer_visit(skip)]
+    local: Space,
+    #[pointer_visit(skip)]
+    world: Space,
+    parent: Option<Pointer<Node>>,
+}
+
+#[derive(PointerVisit)]
+struct Cube {
+    node: Pointer<Node>,
+    material: Pointer<Material>,
+    level: Pointer<Level>,
+}
+
+#[derive(PointerVisit)]
+enum Shape {
+    Empty,
+    Single(Pointer<Node>),
+    Group {
+        nodes: Vec<Pointer<Node>>,
+        #[pointer_visit(skip)]
+        space: Space,
+    },
+    Linked(Pointer<Node>, WeakPointer<Node>),
+}
+
+#[derive(PointerVisit)]
+struct Pair<T>(Pointer<T>, (u32, Option<WeakPointer<T>>));
+
+/// The parameter only appears inside of a pointer, so it needs no bounds.
+#[derive(PointerVisit)]
+struct Painted<M> {
+    material: Pointer<M>,
+}
+
+/// Identity of a visited pointer: the place it's stored at.
+fn addr<T>(pointer: &Pointer<T>) -> *const () {
+    pointer as *const Pointer<T> as *const ()
+}
+
+/// Records the visited strong pointers, and counts the weak ones.
+#[derive(Default)]
+struct Recorder {
+    strong: Vec<*const ()>,
+    weak: usize,
+}
+
+impl PointerVisitor for Recorder {
+    fn visit_pointer<T>(&mut self, pointer: &Pointer<T>) {
+        self.strong.push(addr(pointer));
+    }
+    fn visit_weak<T>(&mut self, _pointer: &WeakPointer<T>) {
+        self.weak += 1;
+    }
+}
+
+fn record<T: PointerVisit>(value: &T) -> Recorder {
+    let mut recorder = Recorder::default();
+    value.visit_pointers(&mut recorder);
+    recorder
+}
+
+fn node(parent: Option<Pointer<Node>>) -> Node {
+    Node {
+        local: Space::default(),
+        world: Space::default(),
+        parent,
+    }
+}
+
+#[test]
+fn structs() {
+    let mut nodes = Storage::new();
+    let mut materials = Storage::new();
+    let mut levels = Storage::new();
+    let root = nodes.create(node(None));
+    let child = nodes.create(node(Some(root.clone())));
+
+    assert!(record(&nodes[&root]).strong.is_empty());
+    let parent = nodes[&child].parent.as_ref().unwrap();
+    assert_eq!(parent, &root);
+    assert_eq!(record(&nodes[&child]).strong, vec![addr(parent)]);
+    assert_eq!(nodes[&child].local._scale, nodes[&child].world._scale);
+
+    let cube = Cube {
+        node: child.clone(),
+        material: materials.create(Material { _color: [1.0; 4] }),
+        level: levels.create(Level { _speed: 1.0 }),
+    };
+    let recorder = record(&cube);
+    assert_eq!(
+        recorder.strong,
+        vec![addr(&cube.node), addr(&cube.material), addr(&cube.level)]
+    );
+    assert_eq!(recorder.weak, 0);
+}
+
+#[test]
+fn enums_and_generics() {
+    let mut nodes = Storage::new();
+    let a = nodes.create(node(None));
+    let b = nodes.create(node(None));
+
+    assert!(record(&Shape::Empty).strong.is_empty());
+    let single = Shape::Single(b.clone());
+    let pointer = match single {
+        Shape::Single(ref pointer) => pointer,
+        _ => unreachable!(),
+    };
+    assert_eq!(record(&single).strong, vec![addr(pointer)]);
+    let group = Shape::Group {
+        nodes: vec![a.clone(), b.clone()],
+        space: Space::default(),
+    };
+    let pointers = match group {
+        Shape::Group { ref nodes, .. } => nodes,
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        record(&group).strong,
+        vec![addr(&pointers[0]), addr(&pointers[1])]
+    );
+    let linked = Shape::Linked(a.clone(), b.downgrade());
+    let pointer = match linked {
+        Shape::Linked(ref pointer, _) => pointer,
+        _ => unreachable!(),
+    };
+    let recorder = record(&linked);
+    assert_eq!(recorder.strong, vec![addr(pointer)]);
+    assert_eq!(recorder.weak, 1);
+
+    let pair = Pair(b.clone(), (5, Some(a.downgrade())));
+    let recorder = record(&pair);
+    assert_eq!(recorder.strong, vec![addr(&pair.0)]);
+    assert_eq!(recorder.weak, 1);
+
+    // `Material` doesn't implement `PointerVisit`
+    let mut materials = Storage::new();
+    let painted = Painted {
+        material: materials.create(Material { _color: [0.5; 4] }),
+    };
+    assert_eq!(record(&painted).strong, vec![addr(&painted.material)]);
+}
+
+#[test]
+fn collect_cycles() {
+    let mut nodes = Storage::new();
+    let a = nodes.create(node(None));
+    let b = nodes.create(node(Some(a.clone())));
+    nodes[&a].parent = Some(b);
+    let kept = nodes.create(node(Some(a.clone())));
+    drop(a);
+
+    assert_eq!(nodes.collect_cycles(), 0);
+    drop(kept);
+    assert_eq!(nodes.collect_cycles(), 2);
+    assert_eq!(nodes.iter().count(), 0);
+}
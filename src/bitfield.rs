@@ -1,37 +1,38 @@
-use crate::{Epoch, Index, StorageId};
+use crate::{Epoch, Index};
 
 #[derive(Copy, Clone, Debug, PartialEq, Hash)]
 pub(crate) struct PointerData(u64);
 
-#[cfg(target_pointer_width = "32")]
-const INDEX_BITS: u8 = 20;
-#[cfg(target_pointer_width = "32")]
+// The storage identity is not a part of the data:
+// it's given by the shared `PendingRef` that every pointer holds.
+
+#[cfg(all(target_pointer_width = "32", not(feature = "epoch-u32")))]
+const INDEX_BITS: u8 = 24;
+#[cfg(all(target_pointer_width = "32", not(feature = "epoch-u32")))]
 const EPOCH_BITS: u8 = 8;
-#[cfg(target_pointer_width = "32")]
-const STORAGE_ID_BITS: u8 = 4;
 
-#[cfg(target_pointer_width = "64")]
-const INDEX_BITS: u8 = 40;
-#[cfg(target_pointer_width = "64")]
+#[cfg(all(target_pointer_width = "64", not(feature = "epoch-u32")))]
+const INDEX_BITS: u8 = 48;
+#[cfg(all(target_pointer_width = "64", not(feature = "epoch-u32")))]
 const EPOCH_BITS: u8 = 16;
-#[cfg(target_pointer_width = "64")]
-const STORAGE_ID_BITS: u8 = 8;
+
+#[cfg(feature = "epoch-u32")]
+const INDEX_BITS: u8 = 32;
+#[cfg(feature = "epoch-u32")]
+const EPOCH_BITS: u8 = 32;
+
+/// The last epoch representable by a pointer. Slots reaching it are retired.
+pub(crate) const MAX_EPOCH: Epoch = ((1u64 << EPOCH_BITS) - 1) as Epoch;
 
 const INDEX_MASK: u64 = (1 << INDEX_BITS) - 1;
 const EPOCH_OFFSET: u8 = INDEX_BITS;
 const EPOCH_MASK: u64 = ((1 << EPOCH_BITS) - 1) << EPOCH_OFFSET;
-const STORAGE_ID_OFFSET: u8 = EPOCH_OFFSET + EPOCH_BITS;
-const STORAGE_ID_MASK: u64 = ((1 << STORAGE_ID_BITS) - 1) << STORAGE_ID_OFFSET;
 
 impl PointerData {
     #[inline]
-    pub fn new(index: Index, epoch: Epoch, storage: StorageId) -> Self {
-        debug_assert_eq!(index >> INDEX_BITS, 0);
-        PointerData(
-            index as u64
-                + ((u64::from(epoch)) << EPOCH_OFFSET)
-                + ((u64::from(storage)) << STORAGE_ID_OFFSET),
-        )
+    pub fn new(index: Index, epoch: Epoch) -> Self {
+        debug_assert_eq!(index as u64 >> INDEX_BITS, 0);
+        PointerData(index as u64 + ((u64::from(epoch)) << EPOCH_OFFSET))
     }
 
     #[inline]
@@ -44,11 +45,6 @@ impl PointerData {
         ((self.0 & EPOCH_MASK) >> EPOCH_OFFSET) as Epoch
     }
 
-    #[inline]
-    pub fn get_storage_id(self) -> StorageId {
-        ((self.0 & STORAGE_ID_MASK) >> STORAGE_ID_OFFSET) as StorageId
-    }
-
     #[inline]
     pub fn with_epoch(self, epoch: Epoch) -> PointerData {
         PointerData((self.0 & !EPOCH_MASK) + ((u64::from(epoch)) << EPOCH_OFFSET))
@@ -62,21 +58,20 @@ mod tests {
 
     #[test]
     fn sizes() {
-        #[cfg(target_pointer_width = "32")]
-        assert_eq!(INDEX_BITS + EPOCH_BITS + STORAGE_ID_BITS, 32);
-        #[cfg(target_pointer_width = "64")]
-        assert_eq!(INDEX_BITS + EPOCH_BITS + STORAGE_ID_BITS, 64);
+        #[cfg(all(target_pointer_width = "32", not(feature = "epoch-u32")))]
+        assert_eq!(INDEX_BITS + EPOCH_BITS, 32);
+        #[cfg(any(target_pointer_width = "64", feature = "epoch-u32"))]
+        assert_eq!(INDEX_BITS + EPOCH_BITS, 64);
         assert!(size_of::<Index>() * 8 >= INDEX_BITS as usize);
         assert!(size_of::<Epoch>() * 8 >= EPOCH_BITS as usize);
-        assert!(size_of::<StorageId>() * 8 >= STORAGE_ID_BITS as usize);
+        assert_eq!(PointerData::new(0, MAX_EPOCH).get_epoch(), MAX_EPOCH);
     }
 
     #[test]
     fn new() {
-        let pd = PointerData::new(1, 2, 3);
+        let pd = PointerData::new(1, 2);
         assert_eq!(pd.get_index(), 1);
         assert_eq!(pd.get_epoch(), 2);
-        assert_eq!(pd.get_storage_id(), 3);
         assert_eq!(pd.with_epoch(5).get_epoch(), 5);
     }
 }
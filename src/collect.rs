@@ -0,0 +1,346 @@
+//! Collection of reference cycles between components.
+//!
+//! Components that point to each other with strong pointers keep each other
+//! alive forever. The collector finds the components that are only referenced
+//! from within the collected storages, and are not reachable from any of the
+//! components referenced from outside, and reclaims them.
+//! The components tell about their pointers by implementing `PointerVisit`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    storage::VACANT, Epoch, Index, Pending, PendingRef, Pointer, PointerData, PointerVisit,
+    PointerVisitor, RefCount, Storage,
+};
+
+/// Reports the strong pointers to the collector.
+struct Tracer<'a> {
+    fun: &'a mut dyn FnMut(*const Pending, Index),
+}
+
+impl<'a> PointerVisitor for Tracer<'a> {
+    #[inline]
+    fn visit_pointer<T>(&mut self, pointer: &Pointer<T>) {
+        (self.fun)(Arc::as_ptr(&pointer.pending), pointer.data.get_index());
+    }
+}
+
+/// Type-erased storage taking part in the collection.
+trait Collect {
+    fn pending(&self) -> &PendingRef;
+    /// Apply the pending updates, while the epochs are locked.
+    fn sync_locked(&mut self, epoch: &mut Vec<Epoch>);
+    fn slot_count(&self) -> usize;
+    fn handle_count(&self) -> usize;
+    fn handle(&self, slot: Index) -> Index;
+    fn slot(&self, handle: Index) -> Index;
+    fn refcount(&self, handle: Index) -> RefCount;
+    fn trace(&self, slot: Index, tracer: &mut Tracer);
+    /// Visit the pointers of the components that are about to be dropped.
+    fn trace_garbage(&self, tracer: &mut Tracer);
+    /// Move the component out, while the epochs are locked.
+    fn take(&mut self, slot: Index, epoch: &mut [Epoch]);
+    /// Drop the components moved out.
+    fn release(&mut self);
+    /// Reclaim the slots, returning the number of dropped components.
+    fn sync(&mut self) -> usize;
+}
+
+struct Collected<'a, T> {
+    storage: &'a mut Storage<T>,
+    /// Components to be dropped, after the locks are released,
+    /// along with the pointer data they had.
+    garbage: Vec<(PointerData, T)>,
+    /// Handles of the spawned components placed by the sync, to be reported.
+    created: Vec<Index>,
+}
+
+impl<'a, T: PointerVisit> Collect for Collected<'a, T> {
+    fn pending(&self) -> &PendingRef {
+        &self.storage.pending
+    }
+
+    fn sync_locked(&mut self, epoch: &mut Vec<Epoch>) {
+        let mut dead = Vec::new();
+        self.storage
+            .sync_locked(epoch, &mut dead, &mut self.created);
+        for slot in dead {
+            let handle = self.storage.inner.handles[slot];
+            let data = PointerData::new(handle, epoch[handle] - 1);
+            self.garbage.push((data, self.storage.inner.vacate(slot)));
+        }
+    }
+
+    fn slot_count(&self) -> usize {
+        self.storage.inner.data.len()
+    }
+
+    fn handle_count(&self) -> usize {
+        self.storage.inner.slots.len()
+    }
+
+    fn handle(&self, slot: Index) -> Index {
+        self.storage.inner.handles[slot]
+    }
+
+    fn slot(&self, handle: Index) -> Index {
+        self.storage.inner.slots[handle]
+    }
+
+    fn refcount(&self, handle: Index) -> RefCount {
+        self.storage.inner.meta.get(handle)
+    }
+
+    fn trace(&self, slot: Index, tracer: &mut Tracer) {
+        let value = unsafe { self.storage.inner.data[slot].assume_init_ref() };
+        value.visit_pointers(tracer);
+    }
+
+    fn trace_garbage(&self, tracer: &mut Tracer) {
+        for (_, value) in &self.garbage {
+            value.visit_pointers(tracer);
+        }
+    }
+
+    fn take(&mut self, slot: Index, epoch: &mut [Epoch]) {
+        // same as `Storage::take`, the pointers to it become stale
+        let handle = self.storage.inner.handles[slot];
+        let data = PointerData::new(handle, epoch[handle]);
+        epoch[handle] += 1;
+        self.garbage.push((data, self.storage.inner.vacate(slot)));
+    }
+
+    fn release(&mut self) {
+        let storage = &mut *self.storage;
+        // the spawned components are reported first, even if they are garbage already
+        for handle in self.created.drain(..) {
+            let data = PointerData::new(handle, 0);
+            let slot = storage.inner.slots[handle];
+            let value = if slot != VACANT {
+                unsafe { storage.inner.data[slot].assume_init_mut() }
+            } else {
+                match self
+                    .garbage
+                    .iter_mut()
+                    .find(|(garbage, _)| garbage.get_index() == handle)
+                {
+                    Some((_, value)) => value,
+                    None => continue,
+                }
+            };
+            storage.hooks.created(&storage.pending, data, value);
+        }
+        for (data, mut value) in self.garbage.drain(..) {
+            storage.hooks.destroyed(&storage.pending, data, &mut value);
+        }
+    }
+
+    fn sync(&mut self) -> usize {
+        self.storage.sync_dropped()
+    }
+}
+
+/// Collector of reference cycles, which can span several storages.
+///
+/// Cycles that go through storages that are not added to the collector are kept.
+/// # Examples
+/// ```rust
+/// # use froggy::{CycleCollector, Pointer, PointerVisit, PointerVisitor, Storage};
+/// struct Parent {
+///     child: Pointer<Child>,
+/// }
+/// impl PointerVisit for Parent {
+///     fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+///         self.child.visit_pointers(visitor);
+///     }
+/// }
+/// struct Child {
+///     parent: Option<Pointer<Parent>>,
+/// }
+/// impl PointerVisit for Child {
+///     fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+///         self.parent.visit_pointers(visitor);
+///     }
+/// }
+///
+/// let mut parents = Storage::new();
+/// let mut children = Storage::new();
+/// let child = children.create(Child { parent: None });
+/// let parent = parents.create(Parent { child: child.clone() });
+/// children[&child].parent = Some(parent);
+/// drop(child);
+///
+/// let mut collector = CycleCollector::new();
+/// collector.add(&mut parents).add(&mut children);
+/// assert_eq!(collector.collect(), 2);
+/// ```
+#[derive(Default)]
+pub struct CycleCollector<'a> {
+    storages: Vec<Box<dyn Collect + 'a>>,
+}
+
+impl<'a> CycleCollector<'a> {
+    /// Create a new collector without any storages.
+    pub fn new() -> Self {
+        CycleCollector {
+            storages: Vec::new(),
+        }
+    }
+
+    /// Add a storage to collect the cycles in.
+    pub fn add<T: PointerVisit>(&mut self, storage: &'a mut Storage<T>) -> &mut Self {
+        self.storages.push(Box::new(Collected {
+            storage,
+            garbage: Vec::new(),
+            created: Vec::new(),
+        }));
+        self
+    }
+
+    /// Reclaim the components that are only referenced by the unreachable cycles,
+    /// returning their number. The pending updates of the storages are synchronized.
+    pub fn collect(&mut self) -> usize {
+        let pendings: Vec<PendingRef> = self
+            .storages
+            .iter()
+            .map(|storage| storage.pending().clone())
+            .collect();
+        let count = {
+            // Weak pointers can't be upgraded while the decision is made,
+            // and the reclaimed components stop being upgradable before the locks are released.
+            let mut epochs: Vec<_> = pendings
+                .iter()
+                .map(|pending| pending.epoch.write())
+                .collect();
+            for (storage, epoch) in self.storages.iter_mut().zip(epochs.iter_mut()) {
+                storage.sync_locked(epoch);
+            }
+            let garbage = self.find_garbage(&pendings);
+            let mut count = 0;
+            for ((storage, epoch), slots) in
+                self.storages.iter_mut().zip(epochs.iter_mut()).zip(garbage)
+            {
+                count += slots.len();
+                for slot in slots {
+                    storage.take(slot, epoch);
+                }
+            }
+            count
+        };
+        // Dropping the components releases their pointers, and may touch any storage.
+        for storage in self.storages.iter_mut() {
+            storage.release();
+        }
+        while self
+            .storages
+            .iter_mut()
+            .map(|storage| storage.sync())
+            .sum::<usize>()
+            != 0
+        {}
+        count
+    }
+
+    /// Return the slots of the unreachable components, for each storage.
+    fn find_garbage(&self, pendings: &[PendingRef]) -> Vec<Vec<Index>> {
+        let ids: HashMap<*const Pending, usize> = pendings
+            .iter()
+            .enumerate()
+            .map(|(id, pending)| (Arc::as_ptr(pending), id))
+            .collect();
+        let storages = &self.storages;
+
+        // count the references coming from the components themselves
+        let mut internal: Vec<Vec<usize>> = storages
+            .iter()
+            .map(|storage| vec![0; storage.handle_count()])
+            .collect();
+        for storage in storages {
+            let mut fun = |pending, handle| {
+                if let Some(&id) = ids.get(&pending) {
+                    internal[id][handle] += 1;
+                }
+            };
+            for slot in 0..storage.slot_count() {
+                if storage.handle(slot) != VACANT {
+                    storage.trace(slot, &mut Tracer { fun: &mut fun });
+                }
+            }
+            // the dead components still hold their pointers
+            storage.trace_garbage(&mut Tracer { fun: &mut fun });
+        }
+
+        // mark everything reachable from the components referenced from outside,
+        // and the unreferenced ones owned by the storage
+        let mut marked: Vec<Vec<bool>> = storages
+            .iter()
+            .map(|storage| vec![false; storage.slot_count()])
+            .collect();
+        let mut stack = Vec::new();
+        for (id, (storage, marked)) in storages.iter().zip(marked.iter_mut()).enumerate() {
+            for (slot, mark) in marked.iter_mut().enumerate() {
+                let handle = storage.handle(slot);
+                if handle == VACANT {
+                    continue;
+                }
+                let count = storage.refcount(handle) as usize;
+                if count == 0 || count > internal[id][handle] {
+                    *mark = true;
+                    stack.push((id, slot));
+                }
+            }
+        }
+        while let Some((id, slot)) = stack.pop() {
+            let mut fun = |pending, handle| {
+                if let Some(&next_id) = ids.get(&pending) {
+                    let next_slot = storages[next_id].slot(handle);
+                    if next_slot != VACANT && !marked[next_id][next_slot] {
+                        marked[next_id][next_slot] = true;
+                        stack.push((next_id, next_slot));
+                    }
+                }
+            };
+            storages[id].trace(slot, &mut Tracer { fun: &mut fun });
+        }
+
+        storages
+            .iter()
+            .zip(marked)
+            .map(|(storage, marked)| {
+                (0..storage.slot_count())
+                    .filter(|&slot| storage.handle(slot) != VACANT && !marked[slot])
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl<T: PointerVisit> Storage<T> {
+    /// Reclaim the components that are only referenced by the unreachable
+    /// cycles within this storage, returning their number.
+    /// See [`CycleCollector`](struct.CycleCollector.html) for cycles across storages.
+    /// # Examples
+    /// ```rust
+    /// # use froggy::{Pointer, PointerVisit, PointerVisitor, Storage};
+    /// struct Node {
+    ///     next: Option<Pointer<Node>>,
+    /// }
+    /// impl PointerVisit for Node {
+    ///     fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+    ///         self.next.visit_pointers(visitor);
+    ///     }
+    /// }
+    ///
+    /// let mut storage = Storage::new();
+    /// let ptr1 = storage.create(Node { next: None });
+    /// let ptr2 = storage.create(Node { next: Some(ptr1.clone()) });
+    /// storage[&ptr1].next = Some(ptr2);
+    /// drop(ptr1);
+    /// assert_eq!(storage.collect_cycles(), 2);
+    /// ```
+    pub fn collect_cycles(&mut self) -> usize {
+        let mut collector = CycleCollector::new();
+        collector.add(self);
+        collector.collect()
+    }
+}
@@ -1,12 +1,18 @@
-use std::{marker::PhantomData, ops};
+use std::{marker::PhantomData, mem::MaybeUninit, ops, sync::Arc};
 
-use crate::{Index, PendingRef, Pointer, PointerData, StorageId, StorageInner};
+use crate::{storage::VACANT, Error, Index, PendingRef, Pointer, PointerData, StorageInner};
 
 /// A slice of a storage. Useful for cursor iteration.
 #[derive(Debug)]
 pub struct Slice<'a, T: 'a> {
-    pub(crate) slice: &'a mut [T],
-    pub(crate) offset: PointerData,
+    pub(crate) slice: &'a mut [MaybeUninit<T>],
+    /// Modification ticks of the slice components.
+    pub(crate) ticks: &'a mut [u64],
+    pub(crate) tick: u64,
+    /// Slots of all the handles in the storage.
+    pub(crate) slots: &'a [Index],
+    pub(crate) offset: Index,
+    pub(crate) pending: &'a PendingRef,
 }
 
 impl<'a, T> Slice<'a, T> {
@@ -18,23 +24,68 @@ impl<'a, T> Slice<'a, T> {
     /// Get a reference by pointer. Returns None if an element
     /// is outside of the slice.
     pub fn get(&'a self, pointer: &Pointer<T>) -> Option<&'a T> {
-        debug_assert_eq!(pointer.data.get_storage_id(), self.offset.get_storage_id());
-        let index = pointer
-            .data
-            .get_index()
-            .wrapping_sub(self.offset.get_index());
-        self.slice.get(index as usize)
+        let index = self.locate(pointer)?;
+        Some(unsafe { self.slice.get_unchecked(index).assume_init_ref() })
     }
 
     /// Get a mutable reference by pointer. Returns None if an element
     /// is outside of the slice.
     pub fn get_mut(&'a mut self, pointer: &Pointer<T>) -> Option<&'a mut T> {
-        debug_assert_eq!(pointer.data.get_storage_id(), self.offset.get_storage_id());
-        let index = pointer
-            .data
-            .get_index()
-            .wrapping_sub(self.offset.get_index());
-        self.slice.get_mut(index as usize)
+        let index = self.locate(pointer)?;
+        self.ticks[index] = self.tick;
+        Some(unsafe { self.slice.get_unchecked_mut(index).assume_init_mut() })
+    }
+
+    /// Get a reference by pointer, or the reason it's not accessible through the slice.
+    /// Unlike [`get`](struct.Slice.html#method.get), this also checks the storage
+    /// and the epoch of the pointer.
+    pub fn try_get(&'a self, pointer: &Pointer<T>) -> Result<&'a T, Error> {
+        let index = self.try_locate(pointer)?;
+        Ok(unsafe { self.slice.get_unchecked(index).assume_init_ref() })
+    }
+
+    /// Get a mutable reference by pointer, or the reason it's not accessible through the slice.
+    /// See [`try_get`](struct.Slice.html#method.try_get).
+    pub fn try_get_mut(&'a mut self, pointer: &Pointer<T>) -> Result<&'a mut T, Error> {
+        let index = self.try_locate(pointer)?;
+        self.ticks[index] = self.tick;
+        Ok(unsafe { self.slice.get_unchecked_mut(index).assume_init_mut() })
+    }
+
+    /// Find the position of the pointed component in the slice, with all the checks.
+    fn try_locate(&self, pointer: &Pointer<T>) -> Result<Index, Error> {
+        if !Arc::ptr_eq(&pointer.pending, self.pending) {
+            return Err(Error::WrongStorage);
+        }
+        let handle = pointer.data.get_index();
+        let slot = match self.slots.get(handle) {
+            Some(&slot) => slot,
+            None => return Err(Error::Uncommitted),
+        };
+        if self.pending.get_epoch(handle) != pointer.data.get_epoch() {
+            return Err(Error::DeadComponent);
+        }
+        // spawned components that are not committed are vacant
+        if slot == VACANT {
+            return Err(Error::Uncommitted);
+        }
+        match slot.wrapping_sub(self.offset) {
+            index if index < self.slice.len() => Ok(index),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Find the position of the pointed component in the slice.
+    fn locate(&self, pointer: &Pointer<T>) -> Option<Index> {
+        debug_assert!(Arc::ptr_eq(&pointer.pending, self.pending));
+        // vacant and uncommitted handles don't fit into any slice
+        let slot = *self.slots.get(pointer.data.get_index())?;
+        let index = slot.wrapping_sub(self.offset);
+        if index < self.slice.len() {
+            Some(index)
+        } else {
+            None
+        }
     }
 }
 
@@ -67,7 +118,7 @@ impl<'a, T> Slice<'a, T> {
 /// }
 /// //...
 /// # fn do_something(_: &Node) {}
-/// # fn try_main() -> Result<(), froggy::DeadComponentError> {
+/// # fn try_main() -> Result<(), froggy::Error> {
 /// # let mut storage = froggy::Storage::new();
 /// # let ptr1 = storage.create(Node { pointer: None });
 /// # let ptr2 = storage.create(Node { pointer: None });
@@ -92,9 +143,9 @@ impl<'a, T> Slice<'a, T> {
 /// ```
 #[derive(Debug)]
 pub struct CursorItem<'a, T: 'a> {
-    item: &'a mut T,
-    pending: &'a PendingRef,
-    data: PointerData,
+    pub(crate) item: &'a mut T,
+    pub(crate) pending: &'a PendingRef,
+    pub(crate) data: PointerData,
 }
 
 impl<'a, T> ops::Deref for CursorItem<'a, T> {
@@ -113,11 +164,8 @@ impl<'a, T> ops::DerefMut for CursorItem<'a, T> {
 impl<'a, T> CursorItem<'a, T> {
     /// Pin the item with a strong pointer.
     pub fn pin(&self) -> Pointer<T> {
-        let epoch = {
-            let mut pending = self.pending.lock();
-            pending.add_ref.push(self.data.get_index());
-            pending.get_epoch(self.data.get_index())
-        };
+        let epoch = self.pending.get_epoch(self.data.get_index());
+        self.pending.acquire(self.data.get_index());
         Pointer {
             data: self.data.with_epoch(epoch),
             pending: self.pending.clone(),
@@ -135,13 +183,14 @@ pub struct Cursor<'a, T: 'a> {
     pub(crate) storage: &'a mut StorageInner<T>,
     pub(crate) pending: &'a PendingRef,
     pub(crate) index: Index,
-    pub(crate) storage_id: StorageId,
+    /// Skip the components that were last modified before this tick.
+    pub(crate) min_tick: u64,
 }
 
 impl<'a, T> Cursor<'a, T> {
-    fn split(&mut self, index: usize) -> (Slice<T>, CursorItem<T>, Slice<T>) {
-        let data = PointerData::new(index, 0, self.storage_id);
-        let (left, item, right) = self.storage.split(data);
+    fn split(&mut self, slot: usize) -> (Slice<T>, CursorItem<T>, Slice<T>) {
+        let data = PointerData::new(self.storage.handles[slot], 0);
+        let (left, item, right) = self.storage.split(slot, self.pending);
         let item = CursorItem {
             item,
             data,
@@ -154,14 +203,12 @@ impl<'a, T> Cursor<'a, T> {
     pub fn next(&mut self) -> Option<(Slice<T>, CursorItem<T>, Slice<T>)> {
         loop {
             let id = self.index;
+            if id >= self.storage.data.len() {
+                return None;
+            }
             self.index += 1;
-            match self.storage.meta.get(id) {
-                None => {
-                    self.index = id; // prevent the bump of the index
-                    return None;
-                }
-                Some(&0) => (),
-                Some(_) => return Some(self.split(id)),
+            if self.storage.is_alive(id) && self.storage.ticks[id] >= self.min_tick {
+                return Some(self.split(id));
             }
         }
     }
@@ -174,8 +221,7 @@ impl<'a, T> Cursor<'a, T> {
             }
             self.index -= 1;
             let id = self.index;
-            debug_assert!(id < self.storage.meta.len());
-            if *unsafe { self.storage.meta.get_unchecked(id) } != 0 {
+            if self.storage.is_alive(id) && self.storage.ticks[id] >= self.min_tick {
                 return Some(self.split(id));
             }
         }
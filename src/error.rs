@@ -0,0 +1,37 @@
+use std::{error, fmt};
+
+/// The error type of the fallible operations on pointers, like upgrading a
+/// [`WeakPointer`](struct.WeakPointer.html), or the `try_` accessors of
+/// [`Storage`](struct.Storage.html) and [`Slice`](struct.Slice.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// The component was destroyed, or taken out of the storage.
+    DeadComponent,
+    /// The pointer belongs to another storage.
+    WrongStorage,
+    /// The component is outside of the accessed slice.
+    OutOfBounds,
+    /// The component is spawned, but not committed to the storage
+    /// by [`sync_pending`](struct.Storage.html#method.sync_pending) yet.
+    Uncommitted,
+    /// The storage of the component was dropped.
+    StorageDropped,
+    /// The component was destroyed, and its handle went through so many components
+    /// that it can't be reused.
+    EpochExhausted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::DeadComponent => "the component is dead",
+            Error::WrongStorage => "the pointer belongs to another storage",
+            Error::OutOfBounds => "the component is out of bounds",
+            Error::Uncommitted => "the component is spawned but not committed",
+            Error::StorageDropped => "the storage is dropped",
+            Error::EpochExhausted => "the component is dead, and its handle is retired",
+        })
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,56 @@
+//! Callbacks on the creation and destruction of components.
+
+use std::{fmt, marker::PhantomData};
+
+use crate::{PendingRef, PointerData, WeakPointer};
+
+type Hook<T> = Box<dyn FnMut(&WeakPointer<T>, &mut T) + Send + Sync>;
+
+/// Callbacks registered on a storage.
+pub(crate) struct Hooks<T> {
+    pub create: Vec<Hook<T>>,
+    pub destroy: Vec<Hook<T>>,
+}
+
+impl<T> Hooks<T> {
+    pub fn new() -> Self {
+        Hooks {
+            create: Vec::new(),
+            destroy: Vec::new(),
+        }
+    }
+
+    fn call(hooks: &mut [Hook<T>], pending: &PendingRef, data: PointerData, value: &mut T) {
+        if hooks.is_empty() {
+            return;
+        }
+        let weak = WeakPointer {
+            data,
+            pending: pending.clone(),
+            marker: PhantomData,
+        };
+        for hook in hooks.iter_mut() {
+            hook(&weak, value);
+        }
+    }
+
+    /// Report a new component.
+    pub fn created(&mut self, pending: &PendingRef, data: PointerData, value: &mut T) {
+        Self::call(&mut self.create, pending, data, value)
+    }
+
+    /// Report a component that is about to be dropped or moved out.
+    /// The epoch of `data` is the one the component had.
+    pub fn destroyed(&mut self, pending: &PendingRef, data: PointerData, value: &mut T) {
+        Self::call(&mut self.destroy, pending, data, value)
+    }
+}
+
+impl<T> fmt::Debug for Hooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("create", &self.create.len())
+            .field("destroy", &self.destroy.len())
+            .finish()
+    }
+}
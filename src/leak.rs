@@ -0,0 +1,116 @@
+//! Diagnostics of the pointers that outlive their storage.
+
+use std::{fmt, sync::atomic::Ordering};
+
+#[cfg(all(not(feature = "atomic-refcount"), feature = "saturating-refcount"))]
+use crate::meta::IMMORTAL;
+use crate::{storage::VACANT, Storage};
+
+/// The components that are still referenced from outside of a storage that goes away.
+/// See [`Storage::into_leak_report`](struct.Storage.html#method.into_leak_report).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    entries: Vec<(usize, usize)>,
+}
+
+impl LeakReport {
+    /// Check if no component is referenced any more.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the handle index and the number of strong pointers
+    /// of each referenced component, ordered by the index.
+    pub fn entries(&self) -> &[(usize, usize)] {
+        &self.entries
+    }
+
+    /// Return the total number of strong pointers.
+    pub fn outstanding(&self) -> usize {
+        self.entries.iter().map(|&(_, count)| count).sum()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} outstanding pointers to {} components",
+            self.outstanding(),
+            self.entries.len()
+        )
+    }
+}
+
+impl<T> Storage<T> {
+    /// Consume the storage, reporting the components that are still referenced
+    /// by strong pointers. The pointers held by the components themselves,
+    /// and the ones already dropped without a [`sync_pending`](struct.Storage.html#method.sync_pending),
+    /// are not counted.
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let a = storage.create(1);
+    /// let _b = storage.create(2);
+    /// let a2 = a.clone();
+    /// drop(a);
+    /// let report = storage.into_leak_report();
+    /// assert_eq!(report.entries(), &[(0, 1), (1, 1)]);
+    /// assert!(!a2.is_storage_alive());
+    /// ```
+    pub fn into_leak_report(mut self) -> LeakReport {
+        let report = self.leak_report();
+        self.leaks_reported = true;
+        report
+    }
+
+    /// Drop the components, and find the references that remain.
+    /// The counters are brought up to date, so that it can be called again.
+    pub(crate) fn leak_report(&mut self) -> LeakReport {
+        self.pending.alive.store(false, Ordering::Release);
+        // the references held by the components themselves are not leaks
+        for slot in 0..self.inner.data.len() {
+            if self.inner.handles[slot] != VACANT {
+                drop(self.inner.vacate(slot));
+            }
+        }
+        #[cfg(not(feature = "atomic-refcount"))]
+        let (subs, adds) = {
+            let (mut subs, mut adds) = (Vec::new(), Vec::new());
+            while let Some(index) = self.pending.sub_ref.pop() {
+                subs.push(index);
+            }
+            while let Some(index) = self.pending.add_ref.pop() {
+                adds.push(index);
+            }
+            (subs, adds)
+        };
+        // includes the spawned components that are not committed
+        let count = self.pending.next_handle.load(Ordering::Relaxed);
+        self.inner.grow_handles(count);
+        #[cfg(not(feature = "atomic-refcount"))]
+        {
+            let counts = self.inner.meta.counts_mut();
+            for index in adds {
+                counts[index] = counts[index].saturating_add(1);
+            }
+            for index in subs {
+                #[cfg(feature = "saturating-refcount")]
+                {
+                    if counts[index] == IMMORTAL {
+                        continue;
+                    }
+                }
+                // the counters could be broken by an overflow, which is no reason to panic here
+                counts[index] = counts[index].saturating_sub(1);
+            }
+        }
+        let meta = &self.inner.meta;
+        LeakReport {
+            entries: (0..meta.len())
+                .filter(|&index| meta.get(index) != 0)
+                .map(|index| (index, meta.get(index) as usize))
+                .collect(),
+        }
+    }
+}
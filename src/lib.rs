@@ -21,56 +21,131 @@ However, CGS has a number of advantages:
 #![warn(missing_docs)]
 #![doc(html_root_url = "https://docs.rs/froggy/0.4.4")]
 
-use spin::Mutex;
-use std::{
-    sync::{atomic::AtomicUsize, Arc},
-    vec::Drain,
+use crossbeam_queue::SegQueue;
+use spin::RwLock;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize},
+    Arc,
 };
 
 mod bitfield;
+mod collect;
 mod cursor;
+mod error;
+mod hooks;
+mod leak;
+mod meta;
+#[cfg(feature = "rayon")]
+mod par;
 mod pointer;
+mod schedule;
+#[cfg(feature = "serde")]
+mod serialization;
+mod spawn;
 mod storage;
+mod validate;
+mod visit;
+mod world;
 
-use crate::bitfield::PointerData;
+use crate::bitfield::{PointerData, MAX_EPOCH};
 use crate::storage::StorageInner;
 
+pub use crate::collect::CycleCollector;
 pub use crate::cursor::{Cursor, CursorItem, Slice};
-pub use crate::pointer::{DeadComponentError, Pointer, WeakPointer};
-pub use crate::storage::{Item, Iter, IterMut, Storage};
+pub use crate::error::Error;
+pub use crate::leak::LeakReport;
+pub use crate::pointer::{Pointer, WeakPointer};
+pub use crate::schedule::{Access, Scheduler, System};
+#[cfg(feature = "serde")]
+pub use crate::serialization::Registry;
+pub use crate::spawn::Spawner;
+pub use crate::storage::{Item, Iter, IterMut, Permutation, Storage, StorageStats};
+pub use crate::validate::Violation;
+pub use crate::visit::{PointerVisit, PointerVisitor};
+pub use crate::world::{ComponentRef, StorageMut, StorageRef, World};
+#[cfg(feature = "derive")]
+pub use froggy_derive::PointerVisit;
 
 type Index = usize;
 
 /// Reference counter type. It doesn't make sense to allocate too much bit for it in regular applications.
-// TODO: control by a cargo feature
+#[cfg(not(feature = "refcount-u32"))]
 type RefCount = u16;
+#[cfg(feature = "refcount-u32")]
+type RefCount = u32;
 
 /// Epoch type determines the number of overwrites of components in storage.
-// TODO: control by a cargo feature
+#[cfg(not(feature = "epoch-u32"))]
 type Epoch = u16;
-
-type StorageId = u8;
-static STORAGE_UID: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "epoch-u32")]
+type Epoch = u32;
 
 /// Pending reference counts updates.
+///
+/// The queues are lock-free, so that pointers can be cloned and dropped
+/// on many threads at once. The epochs are only modified by the `Storage`,
+/// which holds the write lock while processing the queues.
 #[derive(Debug)]
 struct Pending {
-    add_ref: Vec<Index>,
-    sub_ref: Vec<Index>,
-    epoch: Vec<Epoch>,
+    #[cfg(not(feature = "atomic-refcount"))]
+    add_ref: SegQueue<Index>,
+    /// With `atomic-refcount`, only contains the components
+    /// that lost their last reference.
+    sub_ref: SegQueue<Index>,
+    #[cfg(feature = "atomic-refcount")]
+    refs: meta::RefCounts,
+    /// Number of handles given out, including the ones reserved
+    /// by a `Spawner` that the storage doesn't know about yet.
+    next_handle: AtomicUsize,
+    /// Cleared when the storage is dropped, while the pointers may still be around.
+    alive: AtomicBool,
+    epoch: RwLock<Vec<Epoch>>,
 }
 
 impl Pending {
     #[inline]
-    fn drain_sub(&mut self) -> (Drain<Index>, &mut [Epoch]) {
-        (self.sub_ref.drain(..), self.epoch.as_mut_slice())
+    fn get_epoch(&self, index: usize) -> Epoch {
+        epoch_at(&self.epoch.read(), index)
     }
 
+    /// Add a reference to the component.
     #[inline]
-    fn get_epoch(&self, index: usize) -> Epoch {
-        *self.epoch.get(index).unwrap_or(&0)
+    fn acquire(&self, index: Index) {
+        #[cfg(not(feature = "atomic-refcount"))]
+        self.add_ref.push(index);
+        #[cfg(feature = "atomic-refcount")]
+        self.refs.acquire(index);
+    }
+
+    /// Add a reference to the component, unless it's known to be dead.
+    #[inline]
+    fn try_acquire(&self, index: Index) -> bool {
+        #[cfg(not(feature = "atomic-refcount"))]
+        {
+            self.add_ref.push(index);
+            true
+        }
+        #[cfg(feature = "atomic-refcount")]
+        self.refs.try_acquire(index)
     }
+
+    /// Remove a reference to the component.
+    #[inline]
+    fn release(&self, index: Index) {
+        #[cfg(feature = "atomic-refcount")]
+        {
+            if !self.refs.release(index) {
+                return;
+            }
+        }
+        self.sub_ref.push(index);
+    }
+}
+
+#[inline]
+fn epoch_at(epoch: &[Epoch], index: usize) -> Epoch {
+    *epoch.get(index).unwrap_or(&0)
 }
 
 /// Shared pointer to the pending updates.
-type PendingRef = Arc<Mutex<Pending>>;
+type PendingRef = Arc<Pending>;
@@ -0,0 +1,275 @@
+//! Reference counters of the storage slots.
+//!
+//! By default, the counters are owned by the storage and only change in
+//! `sync_pending`, which applies the updates queued by the pointers.
+//! With the `atomic-refcount` feature, the counters are atomics living in
+//! the shared `Pending`, updated by the pointers directly.
+
+#[cfg(feature = "atomic-refcount")]
+use std::{
+    fmt, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{Index, PendingRef, RefCount};
+
+/// Reference count of components that are never going to die.
+/// Only reachable with the `saturating-refcount` feature.
+pub(crate) const IMMORTAL: RefCount = RefCount::MAX;
+/// Largest regular reference count.
+pub(crate) const MAX_REFCOUNT: RefCount = if cfg!(feature = "saturating-refcount") {
+    IMMORTAL - 1
+} else {
+    RefCount::MAX
+};
+
+/// Reference counters, indexed by slot.
+#[cfg(not(feature = "atomic-refcount"))]
+#[derive(Debug)]
+pub(crate) struct Meta {
+    counts: Vec<RefCount>,
+}
+
+#[cfg(not(feature = "atomic-refcount"))]
+impl Meta {
+    pub fn new(counts: Vec<RefCount>, _pending: &PendingRef) -> Self {
+        Meta { counts }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    #[inline]
+    pub fn get(&self, index: Index) -> RefCount {
+        debug_assert!(index < self.counts.len());
+        *unsafe { self.counts.get_unchecked(index) }
+    }
+
+    #[inline]
+    pub fn set(&mut self, index: Index, count: RefCount) {
+        self.counts[index] = count;
+    }
+
+    /// Extend the counters to `len`, with the new ones at zero.
+    #[inline]
+    pub fn grow(&mut self, len: usize) {
+        self.counts.resize(len, 0);
+    }
+
+    #[inline]
+    pub fn counts_mut(&mut self) -> &mut [RefCount] {
+        &mut self.counts
+    }
+}
+
+/// Reference counters, indexed by slot.
+/// They are stored in the `Pending`, where the pointers can reach them.
+#[cfg(feature = "atomic-refcount")]
+#[derive(Debug)]
+pub(crate) struct Meta {
+    pending: PendingRef,
+    len: usize,
+}
+
+#[cfg(feature = "atomic-refcount")]
+impl Meta {
+    pub fn new(counts: Vec<RefCount>, pending: &PendingRef) -> Self {
+        pending.refs.reserve(counts.len());
+        for (index, count) in counts.iter().enumerate() {
+            pending.refs.get(index).store(*count, Ordering::Relaxed);
+        }
+        Meta {
+            pending: pending.clone(),
+            len: counts.len(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn get(&self, index: Index) -> RefCount {
+        debug_assert!(index < self.len);
+        self.pending.refs.get(index).load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set(&mut self, index: Index, count: RefCount) {
+        self.pending.refs.get(index).store(count, Ordering::Release);
+    }
+
+    /// Extend the counters to `len`. The new ones are not reset,
+    /// since a `Spawner` could have set them already.
+    #[inline]
+    pub fn grow(&mut self, len: usize) {
+        self.pending.refs.reserve(len);
+        self.len = len;
+    }
+}
+
+#[cfg(all(feature = "atomic-refcount", not(feature = "refcount-u32")))]
+type AtomicRefCount = std::sync::atomic::AtomicU16;
+#[cfg(all(feature = "atomic-refcount", feature = "refcount-u32"))]
+type AtomicRefCount = std::sync::atomic::AtomicU32;
+
+/// The first bucket has `1 << FIRST_BUCKET_BITS` counters,
+/// and each next one is twice as large as the previous.
+#[cfg(feature = "atomic-refcount")]
+const FIRST_BUCKET_BITS: u32 = 6;
+#[cfg(feature = "atomic-refcount")]
+const BUCKETS: usize = (usize::BITS - FIRST_BUCKET_BITS) as usize;
+
+/// Atomic reference counters that never move in memory,
+/// so that they can be accessed while more of them get allocated.
+#[cfg(feature = "atomic-refcount")]
+pub(crate) struct RefCounts {
+    buckets: [AtomicPtr<AtomicRefCount>; BUCKETS],
+}
+
+#[cfg(feature = "atomic-refcount")]
+impl RefCounts {
+    pub fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const NULL: AtomicPtr<AtomicRefCount> = AtomicPtr::new(ptr::null_mut());
+        RefCounts {
+            buckets: [NULL; BUCKETS],
+        }
+    }
+
+    #[inline]
+    fn bucket_size(bucket: usize) -> usize {
+        1 << (bucket as u32 + FIRST_BUCKET_BITS)
+    }
+
+    /// Returns the bucket and the offset in it for a given index.
+    #[inline]
+    fn locate(index: Index) -> (usize, usize) {
+        let position = index + (1 << FIRST_BUCKET_BITS);
+        let high = usize::BITS - 1 - position.leading_zeros();
+        ((high - FIRST_BUCKET_BITS) as usize, position - (1 << high))
+    }
+
+    /// Make sure the counters are allocated for all the indices below `count`.
+    pub fn reserve(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let (last, _) = Self::locate(count - 1);
+        for (bucket, slot) in self.buckets[..=last].iter().enumerate() {
+            if !slot.load(Ordering::Acquire).is_null() {
+                continue;
+            }
+            let counters: Box<[AtomicRefCount]> = (0..Self::bucket_size(bucket))
+                .map(|_| AtomicRefCount::new(0))
+                .collect();
+            let new = Box::into_raw(counters) as *mut AtomicRefCount;
+            if slot
+                .compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                // somebody else got it first
+                unsafe { Self::free(new, bucket) };
+            }
+        }
+    }
+
+    unsafe fn free(counters: *mut AtomicRefCount, bucket: usize) {
+        let slice = ptr::slice_from_raw_parts_mut(counters, Self::bucket_size(bucket));
+        drop(Box::from_raw(slice));
+    }
+
+    #[inline]
+    pub fn get(&self, index: Index) -> &AtomicRefCount {
+        let (bucket, offset) = Self::locate(index);
+        let counters = self.buckets[bucket].load(Ordering::Acquire);
+        assert!(
+            !counters.is_null(),
+            "Reference counter {} is not allocated",
+            index
+        );
+        unsafe { &*counters.add(offset) }
+    }
+
+    /// Add a reference to the component.
+    #[inline]
+    pub fn acquire(&self, index: Index) {
+        let _ = self
+            .get(index)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                increment(count, true)
+            });
+    }
+
+    /// Add a reference to the component, unless it's no longer referenced.
+    #[inline]
+    pub fn try_acquire(&self, index: Index) -> bool {
+        self.get(index)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                increment(count, false)
+            })
+            .is_ok()
+    }
+
+    /// Remove a reference to the component, returning `true` if it was the last one.
+    #[inline]
+    pub fn release(&self, index: Index) -> bool {
+        let result = self
+            .get(index)
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |count| {
+                if cfg!(feature = "saturating-refcount") && count == IMMORTAL {
+                    None
+                } else {
+                    debug_assert_ne!(count, 0);
+                    Some(count - 1)
+                }
+            });
+        result == Ok(1)
+    }
+}
+
+#[cfg(feature = "atomic-refcount")]
+#[inline]
+fn increment(count: RefCount, from_zero: bool) -> Option<RefCount> {
+    if cfg!(feature = "saturating-refcount") && count == IMMORTAL {
+        // the update is not needed, but it's not a failure either
+        Some(count)
+    } else if count == 0 && !from_zero {
+        None
+    } else if count < MAX_REFCOUNT {
+        Some(count + 1)
+    } else if cfg!(feature = "saturating-refcount") {
+        Some(IMMORTAL)
+    } else {
+        panic!("Reference count overflow")
+    }
+}
+
+#[cfg(feature = "atomic-refcount")]
+impl Drop for RefCounts {
+    fn drop(&mut self) {
+        for (bucket, slot) in self.buckets.iter_mut().enumerate() {
+            let counters = *slot.get_mut();
+            if !counters.is_null() {
+                unsafe { Self::free(counters, bucket) };
+            }
+        }
+    }
+}
+
+#[cfg(feature = "atomic-refcount")]
+impl fmt::Debug for RefCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let allocated = self
+            .buckets
+            .iter()
+            .take_while(|slot| !slot.load(Ordering::Relaxed).is_null())
+            .count();
+        f.debug_struct("RefCounts")
+            .field("buckets", &allocated)
+            .finish()
+    }
+}
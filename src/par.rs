@@ -0,0 +1,98 @@
+//! Parallel iteration over the storage, enabled by the `rayon` feature.
+
+use rayon::prelude::*;
+
+use crate::{storage::VACANT, CursorItem, PointerData, Storage};
+
+impl<T: Sync> Storage<T> {
+    /// Iterate all components in this storage that are still referenced from outside,
+    /// in parallel.
+    /// ### Attention
+    /// Just like [`iter`](struct.Storage.html#method.iter), this relies on the reference
+    /// counters updated by [`sync_pending`](struct.Storage.html#method.sync_pending).
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &T> {
+        let inner = &self.inner;
+        inner
+            .data
+            .par_iter()
+            .enumerate()
+            .filter_map(move |(index, value)| {
+                if inner.is_alive(index) {
+                    Some(unsafe { value.assume_init_ref() })
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+impl<T: Send> Storage<T> {
+    /// Iterate all components in this storage that are still referenced from outside,
+    /// mutably and in parallel.
+    /// ### Attention
+    /// Just like [`iter_mut`](struct.Storage.html#method.iter_mut), this relies on the reference
+    /// counters updated by [`sync_pending`](struct.Storage.html#method.sync_pending).
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut T> {
+        let inner = &mut self.inner;
+        let (meta, handles, tick) = (&inner.meta, &inner.handles, inner.tick);
+        inner
+            .data
+            .par_iter_mut()
+            .zip(inner.ticks.par_iter_mut())
+            .zip(handles.par_iter())
+            .filter_map(move |((value, value_tick), &handle)| {
+                if handle != VACANT && meta.get(handle) != 0 {
+                    *value_tick = tick;
+                    Some(unsafe { value.assume_init_mut() })
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Iterate all components that are stored, even if not referenced, mutably and in parallel.
+    pub fn par_iter_all_mut(&mut self) -> impl ParallelIterator<Item = &mut T> {
+        let inner = &mut self.inner;
+        let tick = inner.tick;
+        inner
+            .data
+            .par_iter_mut()
+            .zip(inner.ticks.par_iter_mut())
+            .zip(inner.handles.par_iter())
+            .filter_map(move |((value, value_tick), &handle)| {
+                if handle != VACANT {
+                    *value_tick = tick;
+                    Some(unsafe { value.assume_init_mut() })
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Call a function on every component that is still referenced from outside,
+    /// in parallel. Unlike [`par_iter_mut`](struct.Storage.html#method.par_iter_mut),
+    /// the items can be [`pin`](struct.CursorItem.html#method.pin)ned with new pointers.
+    pub fn par_for_each_with_pointer<F>(&mut self, fun: F)
+    where
+        F: Fn(CursorItem<T>) + Sync + Send,
+    {
+        let pending = &self.pending;
+        let inner = &mut self.inner;
+        let (meta, handles, tick) = (&inner.meta, &inner.handles, inner.tick);
+        inner
+            .data
+            .par_iter_mut()
+            .zip(inner.ticks.par_iter_mut())
+            .zip(handles.par_iter())
+            .for_each(|((value, value_tick), &handle)| {
+                if handle != VACANT && meta.get(handle) != 0 {
+                    *value_tick = tick;
+                    fun(CursorItem {
+                        item: unsafe { value.assume_init_mut() },
+                        pending,
+                        data: PointerData::new(handle, 0),
+                    });
+                }
+            });
+    }
+}
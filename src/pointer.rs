@@ -2,14 +2,10 @@ use std::{
     fmt,
     hash::{Hash, Hasher},
     marker::PhantomData,
+    sync::{atomic::Ordering, Arc},
 };
 
-use crate::{Pending, PendingRef, PointerData};
-
-/// The error type which is returned from upgrading
-/// [`WeakPointer`](struct.WeakPointer.html).
-#[derive(Debug, PartialEq)]
-pub struct DeadComponentError;
+use crate::{epoch_at, Error, Pending, PendingRef, PointerData, MAX_EPOCH};
 
 /// A pointer to a component of type `T`.
 /// The component is guaranteed to be accessible for as long as this pointer is alive.
@@ -54,8 +50,8 @@ impl<T> fmt::Debug for Pointer<T> {
             &Pointer {
                 index: self.data.get_index() as usize,
                 epoch: self.data.get_epoch() as usize,
-                storage_id: self.data.get_storage_id() as usize,
-                pending: &self.pending.lock(),
+                storage_id: Arc::as_ptr(&self.pending) as usize,
+                pending: &self.pending,
             },
             f,
         )
@@ -73,11 +69,18 @@ impl<T> Pointer<T> {
             marker: PhantomData,
         }
     }
+
+    /// Check if the storage of the component still exists.
+    /// Once it's dropped, the pointer can't be used for anything but comparisons.
+    #[inline]
+    pub fn is_storage_alive(&self) -> bool {
+        self.pending.alive.load(Ordering::Acquire)
+    }
 }
 
 impl<T> PartialOrd for Pointer<T> {
     fn partial_cmp(&self, other: &Pointer<T>) -> Option<std::cmp::Ordering> {
-        if self.data.get_storage_id() == other.data.get_storage_id() {
+        if Arc::ptr_eq(&self.pending, &other.pending) {
             debug_assert!(
                 self.data.get_index() != other.data.get_index()
                     || self.data.get_epoch() == self.data.get_epoch()
@@ -92,7 +95,7 @@ impl<T> PartialOrd for Pointer<T> {
 impl<T> Clone for Pointer<T> {
     #[inline]
     fn clone(&self) -> Pointer<T> {
-        self.pending.lock().add_ref.push(self.data.get_index());
+        self.pending.acquire(self.data.get_index());
         Pointer {
             data: self.data,
             pending: self.pending.clone(),
@@ -104,7 +107,7 @@ impl<T> Clone for Pointer<T> {
 impl<T> PartialEq for Pointer<T> {
     #[inline]
     fn eq(&self, other: &Pointer<T>) -> bool {
-        self.data == other.data
+        self.data == other.data && Arc::ptr_eq(&self.pending, &other.pending)
     }
 }
 
@@ -119,7 +122,7 @@ impl<T> Hash for Pointer<T> {
 impl<T> Drop for Pointer<T> {
     #[inline]
     fn drop(&mut self) {
-        self.pending.lock().sub_ref.push(self.data.get_index());
+        self.pending.release(self.data.get_index());
     }
 }
 
@@ -138,7 +141,8 @@ impl<T> Drop for Pointer<T> {
 /// storage[&ptr1].next = Some(ptr2.clone());
 /// ```
 ///
-/// To avoid such situations, just replace `Option<Pointer<Node>>` with `Option<WeakPointer<Node>>`
+/// To avoid such situations, just replace `Option<Pointer<Node>>` with `Option<WeakPointer<Node>>`,
+/// or implement `PointerVisit` for `Node` and reclaim the cycles with `Storage::collect_cycles`.
 /// # Example
 ///
 /// ```rust
@@ -151,7 +155,7 @@ impl<T> Drop for Pointer<T> {
 /// You will need to [`upgrade`](struct.WeakPointer.html#method.upgrade) `WeakPointer` to access component in storage
 ///
 /// ```rust
-/// # fn try_main() -> Result<(), froggy::DeadComponentError> {
+/// # fn try_main() -> Result<(), Box<dyn std::error::Error>> {
 /// # let mut storage = froggy::Storage::new();
 /// # let _pointer = storage.create(1i32);
 /// # let weak = _pointer.downgrade();
@@ -162,21 +166,31 @@ impl<T> Drop for Pointer<T> {
 /// ```
 #[derive(Debug)]
 pub struct WeakPointer<T> {
-    data: PointerData,
-    pending: PendingRef,
-    marker: PhantomData<T>,
+    pub(crate) data: PointerData,
+    pub(crate) pending: PendingRef,
+    pub(crate) marker: PhantomData<T>,
 }
 
 impl<T> WeakPointer<T> {
     /// Upgrades the `WeakPointer` to a `Pointer`, if possible.
     /// # Errors
-    /// Returns [`DeadComponentError`](struct.DeadComponentError.html) if the related component in storage was destroyed.
-    pub fn upgrade(&self) -> Result<Pointer<T>, DeadComponentError> {
-        let mut pending = self.pending.lock();
-        if pending.get_epoch(self.data.get_index()) != self.data.get_epoch() {
-            return Err(DeadComponentError);
+    /// Returns [`Error::DeadComponent`](enum.Error.html) if the related component in storage was destroyed,
+    /// or [`Error::StorageDropped`](enum.Error.html) if the storage itself was dropped.
+    pub fn upgrade(&self) -> Result<Pointer<T>, Error> {
+        if !self.pending.alive.load(Ordering::Acquire) {
+            return Err(Error::StorageDropped);
+        }
+        // holding the read lock prevents the storage from
+        // killing the component before our reference is queued
+        let epoch = self.pending.epoch.read();
+        match epoch_at(&epoch, self.data.get_index()) {
+            e if e == self.data.get_epoch() => (),
+            MAX_EPOCH => return Err(Error::EpochExhausted),
+            _ => return Err(Error::DeadComponent),
+        }
+        if !self.pending.try_acquire(self.data.get_index()) {
+            return Err(Error::DeadComponent);
         }
-        pending.add_ref.push(self.data.get_index());
         Ok(Pointer {
             data: self.data,
             pending: self.pending.clone(),
@@ -199,8 +213,14 @@ impl<T> Clone for WeakPointer<T> {
 impl<T> PartialEq for WeakPointer<T> {
     #[inline]
     fn eq(&self, other: &WeakPointer<T>) -> bool {
-        self.data == other.data
+        self.data == other.data && Arc::ptr_eq(&self.pending, &other.pending)
     }
 }
 
 impl<T> Eq for WeakPointer<T> {}
+
+impl<T> Hash for WeakPointer<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+    }
+}
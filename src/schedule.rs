@@ -0,0 +1,194 @@
+//! Running systems over the `World`, in parallel when their accesses allow.
+
+use std::{any::TypeId, fmt};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::World;
+
+/// Set of storages a system reads and writes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Access {
+    reads: Vec<TypeId>,
+    writes: Vec<TypeId>,
+}
+
+impl Access {
+    /// Create an empty access set.
+    pub fn new() -> Self {
+        Access::default()
+    }
+
+    /// Declare reading from `Storage<T>`.
+    pub fn read<T: 'static>(mut self) -> Self {
+        self.reads.push(TypeId::of::<T>());
+        self
+    }
+
+    /// Declare writing to `Storage<T>`.
+    pub fn write<T: 'static>(mut self) -> Self {
+        self.writes.push(TypeId::of::<T>());
+        self
+    }
+
+    /// Check if the two sets can't be used at the same time,
+    /// because one of them writes a storage that the other uses.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.writes
+            .iter()
+            .any(|id| other.reads.contains(id) || other.writes.contains(id))
+            || other.writes.iter().any(|id| self.reads.contains(id))
+    }
+}
+
+/// A pass over the components of a `World`.
+pub trait System: Send {
+    /// Return the storages used by `run`.
+    /// Borrowing any other storage can panic when the systems run in parallel.
+    fn access(&self) -> Access;
+    /// Do the work, borrowing the storages from the world.
+    fn run(&mut self, world: &World);
+}
+
+/// System made of a closure.
+struct FnSystem<F> {
+    access: Access,
+    fun: F,
+}
+
+impl<F: FnMut(&World) + Send> System for FnSystem<F> {
+    fn access(&self) -> Access {
+        self.access.clone()
+    }
+
+    fn run(&mut self, world: &World) {
+        (self.fun)(world)
+    }
+}
+
+/// Runner of the systems, in the order they are added.
+///
+/// The systems are grouped into stages, so that each system runs in a later stage
+/// than all the previously added systems it conflicts with. The systems of a stage
+/// run in parallel with the `rayon` feature, and sequentially otherwise.
+/// The pending updates of the world are synchronized after each stage.
+/// # Examples
+/// ```rust
+/// # use froggy::{Access, Pointer, Scheduler, World};
+/// struct Position(f32);
+/// struct Velocity(f32, Pointer<Position>);
+///
+/// let mut world = World::new();
+/// let pos = world.create(Position(0.0));
+/// let _vel = world.create(Velocity(1.0, pos.clone()));
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler
+///     .add_fn(
+///         Access::new().read::<Velocity>().write::<Position>(),
+///         |world| {
+///             let mut positions = world.storage_mut::<Position>();
+///             for vel in world.storage::<Velocity>().iter() {
+///                 positions[&vel.1].0 += vel.0;
+///             }
+///         },
+///     )
+///     .add_fn(Access::new().write::<Velocity>(), |world| {
+///         for vel in world.storage_mut::<Velocity>().iter_mut() {
+///             vel.0 *= 2.0;
+///         }
+///     });
+/// assert_eq!(scheduler.stage_count(), 2);
+/// scheduler.run(&mut world);
+/// scheduler.run(&mut world);
+/// assert_eq!(world.get(&pos).unwrap().0, 3.0);
+/// ```
+#[derive(Default)]
+pub struct Scheduler {
+    systems: Vec<(Box<dyn System>, Access)>,
+    /// Indices of the systems, grouped by stage.
+    stages: Vec<Vec<usize>>,
+    /// First stage the next system can go to.
+    first_stage: usize,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("stages", &self.stages)
+            .finish()
+    }
+}
+
+impl Scheduler {
+    /// Create a new scheduler without any systems.
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+
+    /// Add a system, to run after all the conflicting systems added before.
+    pub fn add<S: System + 'static>(&mut self, system: S) -> &mut Self {
+        let access = system.access();
+        let mut stage = self.first_stage;
+        for (index, (_, other)) in self.systems.iter().enumerate() {
+            if access.conflicts_with(other) {
+                let other_stage = self
+                    .stages
+                    .iter()
+                    .position(|systems| systems.contains(&index))
+                    .unwrap();
+                stage = stage.max(other_stage + 1);
+            }
+        }
+        if stage == self.stages.len() {
+            self.stages.push(Vec::new());
+        }
+        self.stages[stage].push(self.systems.len());
+        self.systems.push((Box::new(system), access));
+        self
+    }
+
+    /// Add a closure as a system, see [`add`](#method.add).
+    pub fn add_fn<F>(&mut self, access: Access, fun: F) -> &mut Self
+    where
+        F: FnMut(&World) + Send + 'static,
+    {
+        self.add(FnSystem { access, fun })
+    }
+
+    /// Make the systems added after this point run after all the systems before it,
+    /// even if they don't conflict.
+    pub fn add_barrier(&mut self) -> &mut Self {
+        self.first_stage = self.stages.len();
+        self
+    }
+
+    /// Return the number of stages.
+    pub fn stage_count(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Run all the systems, stage by stage.
+    pub fn run(&mut self, world: &mut World) {
+        let mut systems: Vec<Option<&mut Box<dyn System>>> = self
+            .systems
+            .iter_mut()
+            .map(|(system, _)| Some(system))
+            .collect();
+        for stage in &self.stages {
+            let mut current: Vec<_> = stage
+                .iter()
+                .map(|&index| systems[index].take().unwrap())
+                .collect();
+            {
+                let world = &*world;
+                #[cfg(feature = "rayon")]
+                current.par_iter_mut().for_each(|system| system.run(world));
+                #[cfg(not(feature = "rayon"))]
+                current.iter_mut().for_each(|system| system.run(world));
+            }
+            world.sync_all();
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! Serialization of storages and pointers, enabled by the `serde` feature.
+//!
+//! A storage is serialized in the order of its handles, including the vacant ones,
+//! so that pointers can be saved as plain (handle, epoch) pairs.
+//! Deserializing a pointer requires its storage to be known,
+//! which is what the [`Registry`](struct.Registry.html) is for.
+
+use serde::{
+    de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    ser::{Serialize, SerializeStruct, Serializer},
+};
+use spin::Mutex;
+use std::{
+    any::{type_name, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    sync::Arc,
+};
+
+use crate::{
+    epoch_at,
+    meta::{Meta, IMMORTAL},
+    storage::VACANT,
+    Epoch, Index, PendingRef, Pointer, PointerData, RefCount, Storage, StorageInner, WeakPointer,
+    MAX_EPOCH,
+};
+
+/// A storage that pointers can be resolved against.
+#[derive(Clone, Debug)]
+struct Entry {
+    pending: PendingRef,
+    /// Number of handles that pointers can refer to.
+    len: usize,
+    /// Set while the storage is being deserialized, in which case the
+    /// deserialized pointers take over the references it recorded,
+    /// instead of adding new ones.
+    adopt: Option<Arc<Mutex<Adoption>>>,
+}
+
+/// The recorded references of a deserialized storage,
+/// and the pointers that took them over.
+#[derive(Debug)]
+struct Adoption {
+    /// References left to take over, per handle.
+    refcounts: Vec<RefCount>,
+    /// Which handles have a component, known once the data is read.
+    occupied: Option<Vec<bool>>,
+    /// Pointers read as a part of the data, to be checked against it.
+    unchecked: Vec<PointerData>,
+}
+
+impl Adoption {
+    /// Take over a reference, checking that the pointer refers to a component.
+    fn adopt<E: de::Error>(&mut self, data: PointerData, pending: &PendingRef) -> Result<(), E> {
+        let index = data.get_index();
+        let count = &mut self.refcounts[index];
+        if *count == 0 {
+            return Err(E::custom(format_args!(
+                "component {} has more pointers than references",
+                index
+            )));
+        }
+        if !cfg!(feature = "saturating-refcount") || *count != IMMORTAL {
+            *count -= 1;
+        }
+        match self.occupied {
+            Some(ref occupied) => check_alive(occupied, pending, data),
+            None => {
+                self.unchecked.push(data);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check that the adopted pointer refers to a component of the deserialized storage.
+fn check_alive<E: de::Error>(
+    occupied: &[bool],
+    pending: &PendingRef,
+    data: PointerData,
+) -> Result<(), E> {
+    let index = data.get_index();
+    if occupied[index] && pending.get_epoch(index) == data.get_epoch() {
+        Ok(())
+    } else {
+        Err(E::custom(format_args!(
+            "pointer to component {} with epoch {} is stale",
+            index,
+            data.get_epoch()
+        )))
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<HashMap<TypeId, Entry>>> = const { RefCell::new(None) };
+}
+
+/// Deserialization context, resolving pointers to their storages.
+///
+/// There can be only one storage per component type. Storages deserialized
+/// within [`scope`](struct.Registry.html#method.scope) are added automatically,
+/// so that the pointers deserialized after them can refer to them.
+/// Existing storages can be added with [`register`](struct.Registry.html#method.register).
+///
+/// The reference counters of a deserialized storage are restored as they were,
+/// and the deserialized pointers to it take over these references.
+/// Therefore, every pointer that was alive at the time of serialization needs
+/// to be serialized, and [`sync_pending`](struct.Storage.html#method.sync_pending)
+/// should be called before serializing the storage. Pointers to dead components,
+/// and more pointers to a component than the references it recorded, are rejected.
+/// # Examples
+/// ```rust
+/// # use froggy::{Pointer, Registry, Storage};
+/// let mut storage = Storage::new();
+/// let pointer = storage.create(1u32);
+/// let json = serde_json::to_string(&(&storage, &pointer)).unwrap();
+///
+/// let (storage, pointer): (Storage<u32>, Pointer<u32>) = Registry::new()
+///     .scope(|| serde_json::from_str(&json))
+///     .unwrap();
+/// assert_eq!(storage[&pointer], 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct Registry {
+    storages: HashMap<TypeId, Entry>,
+}
+
+impl Registry {
+    /// Create a new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an existing storage, so that pointers to it can be deserialized.
+    /// These pointers add new references to the components, and fail to
+    /// deserialize if the components are dead.
+    ///
+    /// Only the components created before the registration can be referred to.
+    pub fn register<T: 'static>(&mut self, storage: &Storage<T>) {
+        let entry = Entry {
+            pending: storage.pending.clone(),
+            len: storage.inner.slots.len(),
+            adopt: None,
+        };
+        self.storages.insert(TypeId::of::<T>(), entry);
+    }
+
+    /// Run the deserialization within the context of this registry.
+    pub fn scope<R, F: FnOnce() -> R>(&mut self, fun: F) -> R {
+        struct Restore<'a> {
+            storages: &'a mut HashMap<TypeId, Entry>,
+            previous: Option<HashMap<TypeId, Entry>>,
+        }
+        impl<'a> Drop for Restore<'a> {
+            fn drop(&mut self) {
+                let current = ACTIVE.with(|active| active.replace(self.previous.take()));
+                *self.storages = current.unwrap_or_default();
+                // the deserialized storages are regular ones from now on
+                for entry in self.storages.values_mut() {
+                    entry.adopt = None;
+                }
+            }
+        }
+
+        let storages = mem::take(&mut self.storages);
+        let previous = ACTIVE.with(|active| active.replace(Some(storages)));
+        let _restore = Restore {
+            storages: &mut self.storages,
+            previous,
+        };
+        fun()
+    }
+}
+
+/// Run `fun` with the storage of `T` registered in the active registry,
+/// or in a temporary one if there is none.
+fn with_entry<T: 'static, R, F: FnOnce() -> R>(entry: Entry, fun: F) -> R {
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            ACTIVE.with(|active| *active.borrow_mut() = None);
+        }
+    }
+
+    let temporary = ACTIVE.with(|active| {
+        let mut active = active.borrow_mut();
+        let temporary = active.is_none();
+        active
+            .get_or_insert_with(HashMap::new)
+            .insert(TypeId::of::<T>(), entry);
+        temporary
+    });
+    let _reset = if temporary { Some(Reset) } else { None };
+    fun()
+}
+
+/// Find the storage of `T` in the active registry, and check that it has the pointed handle.
+fn lookup<T: 'static, E: de::Error>(index: Index, epoch: Epoch) -> Result<Entry, E> {
+    let entry = ACTIVE
+        .with(|active| {
+            let active = active.borrow();
+            active
+                .as_ref()
+                .and_then(|storages| storages.get(&TypeId::of::<T>()).cloned())
+        })
+        .ok_or_else(|| {
+            E::custom(format_args!(
+                "no storage of `{}` is registered",
+                type_name::<T>()
+            ))
+        })?;
+    if index >= entry.len {
+        return Err(E::custom(format_args!(
+            "pointer index {} is out of bounds",
+            index
+        )));
+    }
+    check_epoch(epoch)?;
+    Ok(entry)
+}
+
+// `MAX_EPOCH` only fills the whole `Epoch` on some platforms
+#[allow(clippy::absurd_extreme_comparisons)]
+fn check_epoch<E: de::Error>(epoch: Epoch) -> Result<(), E> {
+    if epoch > MAX_EPOCH {
+        return Err(E::custom(format_args!("epoch {} is out of range", epoch)));
+    }
+    Ok(())
+}
+
+impl<T> Serialize for Pointer<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.data.get_index(), self.data.get_epoch()).serialize(serializer)
+    }
+}
+
+impl<'de, T: 'static> Deserialize<'de> for Pointer<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (index, epoch) = <(Index, Epoch)>::deserialize(deserializer)?;
+        let entry = lookup::<T, D::Error>(index, epoch)?;
+        let data = PointerData::new(index, epoch);
+        match entry.adopt {
+            Some(adoption) => {
+                adoption.lock().adopt(data, &entry.pending)?;
+                Ok(Pointer {
+                    data,
+                    pending: entry.pending,
+                    marker: PhantomData,
+                })
+            }
+            None => WeakPointer {
+                data,
+                pending: entry.pending,
+                marker: PhantomData,
+            }
+            .upgrade()
+            .map_err(de::Error::custom),
+        }
+    }
+}
+
+impl<T> Serialize for WeakPointer<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.data.get_index(), self.data.get_epoch()).serialize(serializer)
+    }
+}
+
+impl<'de, T: 'static> Deserialize<'de> for WeakPointer<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (index, epoch) = <(Index, Epoch)>::deserialize(deserializer)?;
+        let entry = lookup::<T, D::Error>(index, epoch)?;
+        Ok(WeakPointer {
+            data: PointerData::new(index, epoch),
+            pending: entry.pending,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Components in the order of their handles, serialized as options.
+struct Components<'a, T>(&'a StorageInner<T>);
+
+impl<'a, T: Serialize> Serialize for Components<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let inner = self.0;
+        serializer.collect_seq(inner.slots.iter().map(|&slot| {
+            if slot != VACANT {
+                Some(unsafe { inner.data[slot].assume_init_ref() })
+            } else {
+                None
+            }
+        }))
+    }
+}
+
+/// The storage is serialized as a structure of `refcounts`, `epochs`, and `data`.
+/// The `data` has to come last when deserializing, since the
+/// pointers in it are resolved according to the other fields.
+impl<T: Serialize> Serialize for Storage<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let count = self.inner.slots.len();
+        let refcounts: Vec<RefCount> = (0..count).map(|i| self.inner.meta.get(i)).collect();
+        let epochs: Vec<Epoch> = {
+            let epoch = self.pending.epoch.read();
+            (0..count).map(|i| epoch_at(&epoch, i)).collect()
+        };
+        let mut state = serializer.serialize_struct("Storage", 3)?;
+        state.serialize_field("refcounts", &refcounts)?;
+        state.serialize_field("epochs", &epochs)?;
+        state.serialize_field("data", &Components(&self.inner))?;
+        state.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for Storage<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        const FIELDS: &[&str] = &["refcounts", "epochs", "data"];
+        deserializer.deserialize_struct("Storage", FIELDS, StorageVisitor(PhantomData))
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum Field {
+    Refcounts,
+    Epochs,
+    Data,
+}
+
+struct StorageVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de> + 'static> Visitor<'de> for StorageVisitor<T> {
+    type Value = Storage<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct Storage")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Storage<T>, A::Error> {
+        let refcounts = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let epochs = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        build(refcounts, epochs, || {
+            seq.next_element()?
+                .ok_or_else(|| de::Error::invalid_length(2, &self))
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Storage<T>, A::Error> {
+        let mut refcounts = None;
+        let mut epochs = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                Field::Refcounts => refcounts = Some(map.next_value()?),
+                Field::Epochs => epochs = Some(map.next_value()?),
+                Field::Data => {
+                    let refcounts = refcounts
+                        .ok_or_else(|| de::Error::custom("`refcounts` must precede `data`"))?;
+                    let epochs =
+                        epochs.ok_or_else(|| de::Error::custom("`epochs` must precede `data`"))?;
+                    let storage = build(refcounts, epochs, || map.next_value())?;
+                    if map.next_entry::<Field, IgnoredAny>()?.is_some() {
+                        return Err(de::Error::custom("`data` must be the last field"));
+                    }
+                    return Ok(storage);
+                }
+            }
+        }
+        Err(de::Error::missing_field("data"))
+    }
+}
+
+/// Restore the storage, with its pointers resolved while reading the data.
+fn build<T, E, F>(
+    refcounts: Vec<RefCount>,
+    epochs: Vec<Epoch>,
+    read_data: F,
+) -> Result<Storage<T>, E>
+where
+    T: 'static,
+    E: de::Error,
+    F: FnOnce() -> Result<Vec<Option<T>>, E>,
+{
+    let len = refcounts.len();
+    if epochs.len() != len {
+        return Err(E::invalid_length(
+            epochs.len(),
+            &"as many epochs as refcounts",
+        ));
+    }
+    for &epoch in &epochs {
+        check_epoch(epoch)?;
+    }
+    let pending = Storage::<T>::new_pending(epochs);
+    let adoption = Arc::new(Mutex::new(Adoption {
+        refcounts: refcounts.clone(),
+        occupied: None,
+        unchecked: Vec::new(),
+    }));
+    let meta = Meta::new(refcounts, &pending);
+    let entry = Entry {
+        pending: pending.clone(),
+        len,
+        adopt: Some(adoption.clone()),
+    };
+    let components = with_entry::<T, _, _>(entry, read_data)?;
+    if components.len() != len {
+        return Err(E::invalid_length(
+            components.len(),
+            &"as many components as refcounts",
+        ));
+    }
+    let occupied: Vec<bool> = components.iter().map(Option::is_some).collect();
+    for (index, &is_occupied) in occupied.iter().enumerate() {
+        // the epoch of a component is bumped when it dies, which would overflow here
+        if is_occupied && pending.get_epoch(index) == MAX_EPOCH {
+            return Err(E::custom(format_args!(
+                "component {} is at the retired epoch {}",
+                index, MAX_EPOCH
+            )));
+        }
+    }
+    {
+        // the pointers found after the storage are checked right away
+        let mut adoption = adoption.lock();
+        for data in adoption.unchecked.drain(..) {
+            check_alive(&occupied, &pending, data)?;
+        }
+        adoption.occupied = Some(occupied.clone());
+    }
+    let data = components
+        .into_iter()
+        .map(|component| match component {
+            Some(value) => MaybeUninit::new(value),
+            None => MaybeUninit::uninit(),
+        })
+        .collect();
+    Ok(Storage::from_parts(data, occupied, meta, pending))
+}
@@ -0,0 +1,81 @@
+//! Creation of components without a mutable access to the storage.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{storage::SpawnQueue, PendingRef, Pointer, PointerData};
+
+/// A handle to create components in a storage through a shared reference,
+/// possibly on other threads. Obtained with
+/// [`Storage::spawner`](struct.Storage.html#method.spawner).
+///
+/// The returned pointers are valid right away, but the components are only
+/// placed into the storage by the next
+/// [`sync_pending`](struct.Storage.html#method.sync_pending).
+/// Until then, [`Storage::get`](struct.Storage.html#method.get) returns `None` for them,
+/// and indexing the storage with them is an error. The
+/// [`on_create`](struct.Storage.html#method.on_create) callbacks are called on the commit.
+/// # Examples
+/// ```rust
+/// # let mut storage = froggy::Storage::new();
+/// let spawner = storage.spawner();
+/// let pointers: Vec<_> = (0..4)
+///     .map(|i| {
+///         let spawner = spawner.clone();
+///         std::thread::spawn(move || spawner.spawn(i))
+///     })
+///     .map(|thread| thread.join().unwrap())
+///     .collect();
+/// assert_eq!(storage.get(&pointers[0]), None);
+/// storage.sync_pending();
+/// for (i, pointer) in pointers.iter().enumerate() {
+///     assert_eq!(storage[pointer], i);
+/// }
+/// ```
+pub struct Spawner<T> {
+    pub(crate) pending: PendingRef,
+    pub(crate) queue: Arc<SpawnQueue<T>>,
+}
+
+impl<T> Spawner<T> {
+    /// Queue a new component, returning the `Pointer` to it.
+    pub fn spawn(&self, value: T) -> Pointer<T> {
+        let index = self.pending.next_handle.fetch_add(1, Ordering::Relaxed);
+        // The reference is accounted for before the component is queued,
+        // so that the storage never sees the component without it.
+        #[cfg(not(feature = "atomic-refcount"))]
+        self.pending.add_ref.push(index);
+        #[cfg(feature = "atomic-refcount")]
+        {
+            self.pending.refs.reserve(index + 1);
+            self.pending.refs.get(index).store(1, Ordering::Release);
+        }
+        self.queue.push((index, value));
+        Pointer {
+            data: PointerData::new(index, 0),
+            pending: self.pending.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Spawner<T> {
+    fn clone(&self) -> Self {
+        Spawner {
+            pending: self.pending.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Spawner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("storage_id", &(Arc::as_ptr(&self.pending) as usize))
+            .field("queued", &self.queue.len())
+            .finish()
+    }
+}
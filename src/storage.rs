@@ -1,45 +1,317 @@
-use spin::Mutex;
+use crossbeam_queue::SegQueue;
+use spin::RwLock;
 
+#[cfg(not(feature = "atomic-refcount"))]
+use std::collections::HashMap;
 use std::{
-    iter::FromIterator,
+    cmp::Ordering,
+    iter::{self, FromIterator},
     marker::PhantomData,
-    ops, slice,
-    sync::{atomic::Ordering, Arc},
+    mem::{self, MaybeUninit},
+    ops, ptr, slice,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
 };
 
+#[cfg(not(feature = "atomic-refcount"))]
+use crate::meta::{IMMORTAL, MAX_REFCOUNT};
 use crate::{
-    Cursor, Epoch, Index, Pending, PendingRef, Pointer, PointerData, RefCount, Slice, StorageId,
-    STORAGE_UID,
+    epoch_at, hooks::Hooks, meta::Meta, Cursor, Epoch, Error, Index, Pending, PendingRef, Pointer,
+    PointerData, RefCount, Slice, Spawner, WeakPointer, MAX_EPOCH,
 };
 
+/// Components queued by the spawners, along with their reserved handles.
+pub(crate) type SpawnQueue<T> = SegQueue<(Index, T)>;
+
+/// Marks a slot without a component, or a handle without a slot.
+pub(crate) const VACANT: Index = Index::MAX;
+/// Tick of a new storage. Anything in it is changed since tick 0.
+const FIRST_TICK: u64 = 1;
+
 /// Inner storage data that is locked by `RwLock`.
+///
+/// Pointers refer to the components by their handles, which are
+/// mapped to the slots the components are stored in. This way,
+/// the components can be moved around without affecting the pointers.
 #[derive(Debug)]
 pub(crate) struct StorageInner<T> {
-    pub(crate) data: Vec<T>,
-    pub(crate) meta: Vec<RefCount>,
-    free_list: Vec<PointerData>,
+    /// Component values. A slot is initialized if and only if it has a handle.
+    pub(crate) data: Vec<MaybeUninit<T>>,
+    /// Handle of the component in each slot.
+    pub(crate) handles: Vec<Index>,
+    /// Slot of the component of each handle.
+    pub(crate) slots: Vec<Index>,
+    /// Reference counters, indexed by handle.
+    pub(crate) meta: Meta,
+    pub(crate) free_list: Vec<PointerData>,
+    /// Vacant slots to put new components in.
+    pub(crate) holes: Vec<Index>,
+    /// Number of handles that exhausted their epochs and can't be reused.
+    pub(crate) retired: usize,
+    /// Tick of the last modification of the component in each slot.
+    pub(crate) ticks: Vec<u64>,
+    /// Tick to mark the modifications with.
+    pub(crate) tick: u64,
 }
 
 impl<T> StorageInner<T> {
-    pub(crate) fn split(&mut self, offset: PointerData) -> (Slice<T>, &mut T, Slice<T>) {
-        let sid = offset.get_storage_id();
-        let index = offset.get_index();
-        let (left, temp) = self.data.split_at_mut(index as usize);
+    /// Check if the slot holds a component that is referenced from outside.
+    #[inline]
+    pub(crate) fn is_alive(&self, slot: Index) -> bool {
+        let handle = self.handles[slot];
+        handle != VACANT && self.meta.get(handle) != 0
+    }
+
+    /// Process a handle that lost its last reference.
+    fn free(&mut self, handle: Index, epoch: &mut [Epoch], dead: &mut Vec<Index>) {
+        // taken components already had their epoch bumped
+        let slot = self.slots[handle];
+        if slot != VACANT {
+            epoch[handle] += 1;
+            self.slots[handle] = VACANT;
+            dead.push(slot);
+        }
+        if epoch[handle] == MAX_EPOCH {
+            // reusing the handle would make old weak pointers valid again
+            self.retired += 1;
+        } else {
+            let data = PointerData::new(handle, epoch[handle]);
+            self.free_list.push(data);
+        }
+    }
+
+    /// Put the value into a vacant slot, marked as modified.
+    /// The slot is left without a handle.
+    fn place(&mut self, value: T) -> Index {
+        match self.holes.pop() {
+            Some(slot) => {
+                debug_assert_eq!(self.handles[slot], VACANT);
+                self.data[slot] = MaybeUninit::new(value);
+                self.ticks[slot] = self.tick;
+                slot
+            }
+            None => {
+                self.data.push(MaybeUninit::new(value));
+                self.handles.push(VACANT);
+                self.ticks.push(self.tick);
+                self.data.len() - 1
+            }
+        }
+    }
+
+    /// Make room for the handles below `count`. The new handles have no slot,
+    /// until either `create` or the spawned component commit links them.
+    pub(crate) fn grow_handles(&mut self, count: usize) {
+        if self.slots.len() < count {
+            self.slots.resize(count, VACANT);
+            self.meta.grow(count);
+        }
+        debug_assert_eq!(self.meta.len(), self.slots.len());
+    }
+
+    /// Place the components queued by the spawners, reporting their handles.
+    ///
+    /// This has to go after the reference count updates are collected. A spawner
+    /// queues the initial reference before the component, so that reference may be
+    /// collected without the component, which the handles are grown to cover.
+    /// Any other update comes from the returned pointer, after the component is queued,
+    /// so the component is committed along with it.
+    fn commit_spawned(
+        &mut self,
+        pending: &Pending,
+        spawned: &SpawnQueue<T>,
+        epoch: &mut Vec<Epoch>,
+        created: &mut Vec<Index>,
+    ) {
+        let mut values = Vec::new();
+        while let Some(entry) = spawned.pop() {
+            values.push(entry);
+        }
+        // covers the handles of all the collected updates
+        self.grow_handles(pending.next_handle.load(AtomicOrdering::Relaxed));
+        // missing epochs
+        while epoch.len() < self.slots.len() {
+            epoch.push(0);
+        }
+        for (handle, value) in values {
+            let slot = self.place(value);
+            self.handles[slot] = handle;
+            self.slots[handle] = slot;
+            created.push(handle);
+        }
+    }
+
+    /// Apply the pending reference count updates,
+    /// collecting the components that need to be dropped.
+    #[cfg(not(feature = "atomic-refcount"))]
+    fn sync_counts(
+        &mut self,
+        pending: &Pending,
+        spawned: &SpawnQueue<T>,
+        epoch: &mut Vec<Epoch>,
+        dead: &mut Vec<Index>,
+        created: &mut Vec<Index>,
+    ) {
+        let mut subs = Vec::new();
+        let mut adds = Vec::new();
+        let mut overflow = HashMap::new();
+        // The subs are collected before the adds: if a pointer got cloned
+        // and then the original dropped, the add is guaranteed to be seen.
+        while let Some(index) = pending.sub_ref.pop() {
+            subs.push(index);
+        }
+        while let Some(index) = pending.add_ref.pop() {
+            adds.push(index);
+        }
+        self.commit_spawned(pending, spawned, epoch, created);
+        let counts = self.meta.counts_mut();
+        // pending reference adds
+        for index in adds {
+            let count = &mut counts[index];
+            if cfg!(feature = "saturating-refcount") && *count == IMMORTAL {
+                continue;
+            } else if *count < MAX_REFCOUNT {
+                *count += 1;
+            } else {
+                // can still be compensated by the subs
+                *overflow.entry(index).or_insert(0) += 1;
+            }
+        }
+        // pending reference subs
+        for index in subs {
+            match overflow.get_mut(&index) {
+                Some(extra) if *extra != 0 => {
+                    *extra -= 1;
+                    continue;
+                }
+                _ => (),
+            }
+            let counts = self.meta.counts_mut();
+            if cfg!(feature = "saturating-refcount") && counts[index] == IMMORTAL {
+                continue;
+            }
+            counts[index] -= 1;
+            if counts[index] == 0 {
+                self.free(index, epoch, dead);
+            }
+        }
+        for (index, extra) in overflow {
+            if extra == 0 {
+                continue;
+            }
+            if cfg!(feature = "saturating-refcount") {
+                self.meta.set(index, IMMORTAL);
+            } else {
+                panic!("Reference count overflow for component {}", index);
+            }
+        }
+    }
+
+    /// Find the components that lost their last reference,
+    /// collecting the ones that need to be dropped.
+    #[cfg(feature = "atomic-refcount")]
+    fn sync_counts(
+        &mut self,
+        pending: &Pending,
+        spawned: &SpawnQueue<T>,
+        epoch: &mut Vec<Epoch>,
+        dead: &mut Vec<Index>,
+        created: &mut Vec<Index>,
+    ) {
+        let mut zeroes = Vec::new();
+        while let Some(index) = pending.sub_ref.pop() {
+            zeroes.push(index);
+        }
+        self.commit_spawned(pending, spawned, epoch, created);
+        // A component could be pinned again and released
+        // after it lost the last reference, reporting it twice.
+        zeroes.sort_unstable();
+        zeroes.dedup();
+        for index in zeroes {
+            if self.meta.get(index) == 0 {
+                self.free(index, epoch, dead);
+            }
+        }
+    }
+
+    /// Move the component out of the slot, detaching it from its handle.
+    pub(crate) fn vacate(&mut self, slot: Index) -> T {
+        let handle = mem::replace(&mut self.handles[slot], VACANT);
+        debug_assert_ne!(handle, VACANT);
+        self.slots[handle] = VACANT;
+        self.holes.push(slot);
+        unsafe { ptr::read(self.data[slot].as_ptr()) }
+    }
+
+    /// Rearrange the components, so that the one in slot `order[i]` moves to slot `i`.
+    /// The order has to list every occupied slot once, and the vacant slots are cut off.
+    pub(crate) fn permute(&mut self, order: &[Index]) {
+        let mut data = Vec::with_capacity(self.data.capacity());
+        let mut handles = Vec::with_capacity(self.handles.capacity());
+        let mut ticks = Vec::with_capacity(self.ticks.capacity());
+        for (slot, &old) in order.iter().enumerate() {
+            // vacate the old slot, so that it's neither moved twice, nor dropped
+            let handle = mem::replace(&mut self.handles[old], VACANT);
+            assert_ne!(handle, VACANT, "Slot {} is not occupied", old);
+            data.push(MaybeUninit::new(unsafe {
+                ptr::read(self.data[old].as_ptr())
+            }));
+            handles.push(handle);
+            ticks.push(self.ticks[old]);
+            self.slots[handle] = slot;
+        }
+        debug_assert!(self.handles.iter().all(|&handle| handle == VACANT));
+        self.data = data;
+        self.handles = handles;
+        self.ticks = ticks;
+        self.holes.clear();
+    }
+
+    pub(crate) fn split<'a>(
+        &'a mut self,
+        slot: Index,
+        pending: &'a PendingRef,
+    ) -> (Slice<'a, T>, &'a mut T, Slice<'a, T>) {
+        debug_assert_ne!(self.handles[slot], VACANT);
+        let (left, temp) = self.data.split_at_mut(slot);
         let (cur, right) = temp.split_at_mut(1);
+        let (left_ticks, temp) = self.ticks.split_at_mut(slot);
+        let (cur_tick, right_ticks) = temp.split_at_mut(1);
+        cur_tick[0] = self.tick;
         (
             Slice {
                 slice: left,
-                offset: PointerData::new(0, 0, sid),
+                ticks: left_ticks,
+                tick: self.tick,
+                slots: &self.slots,
+                offset: 0,
+                pending,
             },
-            unsafe { cur.get_unchecked_mut(0) },
+            unsafe { cur.get_unchecked_mut(0).assume_init_mut() },
             Slice {
                 slice: right,
-                offset: PointerData::new(index + 1, 0, sid),
+                ticks: right_ticks,
+                tick: self.tick,
+                slots: &self.slots,
+                offset: slot + 1,
+                pending,
             },
         )
     }
 }
 
+impl<T> Drop for StorageInner<T> {
+    fn drop(&mut self) {
+        for (value, &handle) in self.data.iter_mut().zip(&self.handles) {
+            if handle != VACANT {
+                unsafe { ptr::drop_in_place(value.as_mut_ptr()) };
+            }
+        }
+    }
+}
+
 /// Component storage type.
 /// Manages the components and allows for efficient processing.
 /// See also: [Pointer](struct.Pointer.html)
@@ -54,27 +326,48 @@ impl<T> StorageInner<T> {
 /// ```
 #[derive(Debug)]
 pub struct Storage<T> {
-    inner: StorageInner<T>,
-    pending: PendingRef,
-    id: StorageId,
+    pub(crate) inner: StorageInner<T>,
+    pub(crate) pending: PendingRef,
+    pub(crate) hooks: Hooks<T>,
+    pub(crate) spawned: Arc<SpawnQueue<T>>,
+    /// Set once the leaks are reported to the user, so that they are not reported on drop.
+    pub(crate) leaks_reported: bool,
+}
+
+impl<T> Drop for Storage<T> {
+    fn drop(&mut self) {
+        if cfg!(feature = "leak-report") && !self.leaks_reported {
+            let report = self.leak_report();
+            if !report.is_empty() {
+                eprintln!(
+                    "froggy: storage of `{}` dropped with {}",
+                    std::any::type_name::<T>(),
+                    report
+                );
+            }
+        }
+        // the remaining weak pointers should not be upgraded any more
+        self.pending.alive.store(false, AtomicOrdering::Release);
+    }
 }
 
 impl<'a, T> ops::Index<&'a Pointer<T>> for Storage<T> {
     type Output = T;
     #[inline]
     fn index(&self, pointer: &'a Pointer<T>) -> &T {
-        debug_assert_eq!(pointer.data.get_storage_id(), self.id);
-        debug_assert!(pointer.data.get_index() < self.inner.data.len());
-        unsafe { self.inner.data.get_unchecked(pointer.data.get_index()) }
+        let slot = self.index_slot(pointer);
+        unsafe { self.inner.data.get_unchecked(slot).assume_init_ref() }
     }
 }
 
 impl<'a, T> ops::IndexMut<&'a Pointer<T>> for Storage<T> {
     #[inline]
     fn index_mut(&mut self, pointer: &'a Pointer<T>) -> &mut T {
-        debug_assert_eq!(pointer.data.get_storage_id(), self.id);
-        debug_assert!(pointer.data.get_index() < self.inner.data.len());
-        unsafe { self.inner.data.get_unchecked_mut(pointer.data.get_index()) }
+        let slot = self.index_slot(pointer);
+        unsafe {
+            *self.inner.ticks.get_unchecked_mut(slot) = self.inner.tick;
+            self.inner.data.get_unchecked_mut(slot).assume_init_mut()
+        }
     }
 }
 
@@ -83,7 +376,7 @@ impl<T> FromIterator<T> for Storage<T> {
     where
         I: IntoIterator<Item = T>,
     {
-        let data: Vec<T> = iter.into_iter().collect();
+        let data: Vec<_> = iter.into_iter().map(MaybeUninit::new).collect();
         let count = data.len();
         Storage::new_impl(data, vec![0; count], vec![0; count])
     }
@@ -96,6 +389,7 @@ impl<'a, T> IntoIterator for &'a Storage<T> {
         Iter {
             storage: &self.inner,
             skip_lost: true,
+            min_tick: 0,
             index: 0,
         }
     }
@@ -110,22 +404,80 @@ impl<'a, T> IntoIterator for &'a mut Storage<T> {
 }
 
 impl<T> Storage<T> {
-    fn new_impl(data: Vec<T>, meta: Vec<RefCount>, epoch: Vec<Epoch>) -> Storage<T> {
+    fn new_impl(data: Vec<MaybeUninit<T>>, meta: Vec<RefCount>, epoch: Vec<Epoch>) -> Storage<T> {
         assert_eq!(data.len(), meta.len());
         assert!(epoch.len() <= meta.len());
-        let uid = STORAGE_UID.fetch_add(1, Ordering::Relaxed) as StorageId;
+        let occupied = vec![true; data.len()];
+        let pending = Self::new_pending(epoch);
+        let meta = Meta::new(meta, &pending);
+        Self::from_parts(data, occupied, meta, pending)
+    }
+
+    pub(crate) fn new_pending(epoch: Vec<Epoch>) -> PendingRef {
+        Arc::new(Pending {
+            #[cfg(not(feature = "atomic-refcount"))]
+            add_ref: SegQueue::new(),
+            sub_ref: SegQueue::new(),
+            #[cfg(feature = "atomic-refcount")]
+            refs: crate::meta::RefCounts::new(),
+            next_handle: Default::default(),
+            alive: AtomicBool::new(true),
+            epoch: RwLock::new(epoch),
+        })
+    }
+
+    /// Assemble the storage, with each component stored in the slot matching its handle.
+    /// The vacant unreferenced handles are collected into the free list.
+    pub(crate) fn from_parts(
+        data: Vec<MaybeUninit<T>>,
+        occupied: Vec<bool>,
+        meta: Meta,
+        pending: PendingRef,
+    ) -> Storage<T> {
+        let mut slots = Vec::with_capacity(data.capacity());
+        slots.extend((0..data.len()).map(|i| if occupied[i] { i } else { VACANT }));
+        let mut handles = Vec::with_capacity(data.capacity());
+        handles.extend_from_slice(&slots);
+        let mut ticks = Vec::with_capacity(data.capacity());
+        ticks.resize(data.len(), FIRST_TICK);
+        let mut free_list = Vec::new();
+        let mut holes = Vec::new();
+        let mut retired = 0;
+        pending
+            .next_handle
+            .store(slots.len(), AtomicOrdering::Relaxed);
+        {
+            let epoch = pending.epoch.read();
+            for index in (0..data.len()).rev() {
+                if occupied[index] {
+                    continue;
+                }
+                holes.push(index);
+                if meta.get(index) != 0 {
+                    continue;
+                }
+                match epoch_at(&epoch, index) {
+                    MAX_EPOCH => retired += 1,
+                    e => free_list.push(PointerData::new(index, e)),
+                }
+            }
+        }
         Storage {
             inner: StorageInner {
                 data,
+                handles,
+                slots,
                 meta,
-                free_list: Vec::new(),
+                free_list,
+                holes,
+                retired,
+                ticks,
+                tick: FIRST_TICK,
             },
-            pending: Arc::new(Mutex::new(Pending {
-                add_ref: Vec::new(),
-                sub_ref: Vec::new(),
-                epoch,
-            })),
-            id: uid,
+            pending,
+            hooks: Hooks::new(),
+            spawned: Arc::new(SegQueue::new()),
+            leaks_reported: false,
         }
     }
 
@@ -147,30 +499,511 @@ impl<T> Storage<T> {
     /// It will update all reference counters in Storage, so
     /// [`iter_alive`](struct.Storage.html#method.iter_alive) and
     /// [`iter_alive_mut`](struct.Storage.html#method.iter_alive_mut) will return actual information.
+    /// Components that are no longer referenced are dropped here.
+    /// With the `atomic-refcount` feature, the counters are already up to date,
+    /// and this only reclaims the slots of the components that lost their last reference.
     ///
     /// Use this function only if necessary, because it needs to block Storage.
     pub fn sync_pending(&mut self) {
-        let mut pending = self.pending.lock();
-        // missing epochs
-        while pending.epoch.len() < self.inner.data.len() {
-            pending.epoch.push(0);
+        self.sync_dropped();
+    }
+
+    /// Synchronize the pending updates, returning the number of dropped components.
+    pub(crate) fn sync_dropped(&mut self) -> usize {
+        let mut dead = Vec::new();
+        let mut created = Vec::new();
+        let mut count = 0;
+        loop {
+            {
+                let pending = self.pending.clone();
+                self.sync_locked(&mut pending.epoch.write(), &mut dead, &mut created);
+            }
+            // the spawned components are reported before any of them is dropped
+            for handle in created.drain(..) {
+                let slot = match self.inner.slots[handle] {
+                    // died before the commit, but the slot still knows the handle
+                    VACANT => *dead
+                        .iter()
+                        .find(|&&slot| self.inner.handles[slot] == handle)
+                        .unwrap(),
+                    slot => slot,
+                };
+                let data = PointerData::new(handle, 0);
+                let value = unsafe { self.inner.data[slot].assume_init_mut() };
+                self.hooks.created(&self.pending, data, value);
+            }
+            if dead.is_empty() {
+                return count;
+            }
+            count += dead.len();
+            // Dropping the components may release pointers into this very storage,
+            // so it has to happen outside of the lock, followed by another round.
+            for slot in dead.drain(..) {
+                let handle = self.inner.handles[slot];
+                let mut value = self.inner.vacate(slot);
+                // the epoch got bumped when the component died
+                let data = PointerData::new(handle, self.pending.get_epoch(handle) - 1);
+                self.hooks.destroyed(&self.pending, data, &mut value);
+            }
         }
-        // pending reference adds
-        for index in pending.add_ref.drain(..) {
-            self.inner.meta[index] += 1;
+    }
+
+    /// Apply the pending updates while holding the epoch lock,
+    /// collecting the slots of the components that need to be dropped,
+    /// and the handles of the spawned components that got placed.
+    pub(crate) fn sync_locked(
+        &mut self,
+        epoch: &mut Vec<Epoch>,
+        dead: &mut Vec<Index>,
+        created: &mut Vec<Index>,
+    ) {
+        self.inner
+            .sync_counts(&self.pending, &self.spawned, epoch, dead, created);
+    }
+
+    /// Return a handle to create components through a shared reference.
+    /// See [`Spawner`](struct.Spawner.html).
+    pub fn spawner(&self) -> Spawner<T> {
+        Spawner {
+            pending: self.pending.clone(),
+            queue: self.spawned.clone(),
         }
-        // pending reference subs
+    }
+
+    /// Return the number of handles that went through so many components that
+    /// their epochs got exhausted. These handles are never reused, so that
+    /// any `WeakPointer` to them fails to upgrade.
+    pub fn retired_count(&self) -> usize {
+        self.inner.retired
+    }
+
+    /// Gather the statistics of the storage, without changing anything.
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let _a = storage.create(1);
+    /// drop(storage.create(2));
+    /// let stats = storage.stats();
+    /// assert_eq!((stats.slots, stats.pending_subs), (2, 1));
+    /// storage.sync_pending();
+    /// let stats = storage.stats();
+    /// assert_eq!((stats.live, stats.free_handles, stats.fragmentation), (1, 1, 0.5));
+    /// ```
+    pub fn stats(&self) -> StorageStats {
+        let inner = &self.inner;
+        let mut stats = StorageStats {
+            slots: inner.data.len(),
+            free_handles: inner.free_list.len(),
+            retired: inner.retired,
+            #[cfg(not(feature = "atomic-refcount"))]
+            pending_adds: self.pending.add_ref.len(),
+            pending_subs: self.pending.sub_ref.len(),
+            pending_spawns: self.spawned.len(),
+            ..StorageStats::default()
+        };
+        let mut vacant = 0;
+        for &handle in &inner.handles {
+            if handle == VACANT {
+                vacant += 1;
+            } else if inner.meta.get(handle) != 0 {
+                stats.live += 1;
+            }
+        }
+        stats.max_refcount = (0..inner.meta.len())
+            .map(|handle| inner.meta.get(handle) as usize)
+            .max()
+            .unwrap_or(0);
+        if stats.slots != 0 {
+            stats.fragmentation = vacant as f32 / stats.slots as f32;
+        }
+        stats
+    }
+
+    /// Return the tick that the modifications are currently marked with.
+    /// A new storage starts at tick 1, so all of its components are changed since tick 0.
+    pub fn current_tick(&self) -> u64 {
+        self.inner.tick
+    }
+
+    /// Advance the tick that the modifications are marked with, returning the new one.
+    ///
+    /// The components get marked when they are created, and when they are accessed mutably:
+    /// by `IndexMut`, [`get_mut`](struct.Storage.html#method.get_mut),
+    /// [`iter_mut`](struct.Storage.html#method.iter_mut) and the cursor items.
+    pub fn advance_tick(&mut self) -> u64 {
+        self.inner.tick += 1;
+        self.inner.tick
+    }
+
+    /// Iterate the components that are still referenced from outside,
+    /// and were modified after the given tick.
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let a = storage.create(1);
+    /// let b = storage.create(2);
+    /// let uploaded = storage.current_tick();
+    /// storage.advance_tick();
+    /// storage[&b] += 1;
+    /// let changed: Vec<_> = storage.iter_changed_since(uploaded).map(|item| *item).collect();
+    /// assert_eq!(changed, vec![3]);
+    /// ```
+    pub fn iter_changed_since(&self, tick: u64) -> Iter<'_, T> {
+        Iter {
+            storage: &self.inner,
+            skip_lost: true,
+            min_tick: tick.saturating_add(1),
+            index: 0,
+        }
+    }
+
+    /// Register a callback for every new component, called by
+    /// [`create`](struct.Storage.html#method.create) right after the component is placed.
+    /// The components of a [`Spawner`](struct.Spawner.html) are reported by
+    /// [`sync_pending`](struct.Storage.html#method.sync_pending), when they get placed.
+    pub fn on_create<F>(&mut self, hook: F)
+    where
+        F: FnMut(&WeakPointer<T>, &mut T) + Send + Sync + 'static,
+    {
+        self.hooks.create.push(Box::new(hook));
+    }
+
+    /// Register a callback for every component that is about to be dropped by
+    /// [`sync_pending`](struct.Storage.html#method.sync_pending), or moved out by
+    /// [`take`](struct.Storage.html#method.take). The weak pointer can't be upgraded
+    /// any more, but it's equal to the ones downgraded from the component pointers.
+    /// The components still alive when the storage is dropped are not reported.
+    /// # Examples
+    /// ```rust
+    /// # use froggy::Storage;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let dead = Arc::new(Mutex::new(Vec::new()));
+    /// let mut storage = Storage::new();
+    /// let dead_clone = dead.clone();
+    /// storage.on_destroy(move |_, value: &mut i32| dead_clone.lock().unwrap().push(*value));
+    ///
+    /// drop(storage.create(3));
+    /// let pointer = storage.create(5);
+    /// storage.sync_pending();
+    /// storage.remove(&pointer);
+    /// assert_eq!(*dead.lock().unwrap(), vec![3, 5]);
+    /// ```
+    pub fn on_destroy<F>(&mut self, hook: F)
+    where
+        F: FnMut(&WeakPointer<T>, &mut T) + Send + Sync + 'static,
+    {
+        self.hooks.destroy.push(Box::new(hook));
+    }
+
+    /// Check if the pointer refers to a component that is still in this storage.
+    /// This is only `false` for pointers from other storages, and for
+    /// the stale ones, whose component got [`take`](struct.Storage.html#method.take)n.
+    pub fn contains(&self, pointer: &Pointer<T>) -> bool {
+        self.locate(pointer).is_ok()
+    }
+
+    /// Find the slot of the pointed component.
+    fn locate(&self, pointer: &Pointer<T>) -> Result<Index, Error> {
+        if !Arc::ptr_eq(&pointer.pending, &self.pending) {
+            return Err(Error::WrongStorage);
+        }
+        self.locate_data(pointer.data)
+    }
+
+    /// Find the slot of the component, assuming the pointer belongs to this storage.
+    fn locate_data(&self, data: PointerData) -> Result<Index, Error> {
+        let index = data.get_index();
+        // the storage doesn't know the handles reserved by the spawners yet
+        let slot = match self.inner.slots.get(index) {
+            Some(&slot) => slot,
+            None => return Err(Error::Uncommitted),
+        };
+        if self.pending.get_epoch(index) != data.get_epoch() {
+            Err(Error::DeadComponent)
+        } else if slot == VACANT {
+            // the handle is known, but the spawned component is not committed yet
+            Err(Error::Uncommitted)
+        } else {
+            Ok(slot)
+        }
+    }
+
+    /// Find the slot of the pointed component for indexing, panicking if it's not there.
+    /// The storage and the epoch of the pointer are only checked in debug builds,
+    /// or with the `checked-index` feature. A strong pointer into this storage keeps
+    /// its handle from being reused, so a vacant slot is the only stale case otherwise.
+    #[inline]
+    fn index_slot(&self, pointer: &Pointer<T>) -> Index {
+        if cfg!(any(debug_assertions, feature = "checked-index")) {
+            return match self.locate(pointer) {
+                Ok(slot) => slot,
+                Err(e) => panic!("Invalid pointer: {}", e),
+            };
+        }
+        match self.inner.slots.get(pointer.data.get_index()) {
+            Some(&slot) if slot != VACANT => slot,
+            Some(_) => panic!("Invalid pointer: {}", Error::DeadComponent),
+            None => panic!("Invalid pointer: {}", Error::Uncommitted),
+        }
+    }
+
+    /// Get a reference to the component, if the pointer is valid for this storage.
+    /// Unlike indexing, this checks the storage, the bounds, and the epoch of the pointer
+    /// in all builds.
+    pub fn get(&self, pointer: &Pointer<T>) -> Option<&T> {
+        self.try_get(pointer).ok()
+    }
+
+    /// Get a mutable reference to the component, if the pointer is valid for this storage.
+    /// See [`get`](struct.Storage.html#method.get).
+    pub fn get_mut(&mut self, pointer: &Pointer<T>) -> Option<&mut T> {
+        self.try_get_mut(pointer).ok()
+    }
+
+    /// Get a reference to the component, or the reason the pointer is not valid
+    /// for this storage. See [`get`](struct.Storage.html#method.get).
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let pointer = storage.create(1);
+    /// storage.remove(&pointer);
+    /// assert_eq!(storage.try_get(&pointer), Err(froggy::Error::DeadComponent));
+    /// ```
+    pub fn try_get(&self, pointer: &Pointer<T>) -> Result<&T, Error> {
+        let slot = self.locate(pointer)?;
+        Ok(unsafe { self.inner.data.get_unchecked(slot).assume_init_ref() })
+    }
+
+    /// Get a mutable reference to the component, or the reason the pointer is not valid
+    /// for this storage. See [`try_get`](struct.Storage.html#method.try_get).
+    pub fn try_get_mut(&mut self, pointer: &Pointer<T>) -> Result<&mut T, Error> {
+        let slot = self.locate(pointer)?;
+        self.inner.ticks[slot] = self.inner.tick;
+        Ok(unsafe { self.inner.data.get_unchecked_mut(slot).assume_init_mut() })
+    }
+
+    /// Move the component out of the storage, leaving its slot vacant.
+    /// All the `WeakPointer`s to it will fail to upgrade, and the remaining strong
+    /// `Pointer`s become stale, which can be checked with
+    /// [`contains`](struct.Storage.html#method.contains).
+    /// The slot is not reused until all of them are dropped.
+    ///
+    /// # Panics
+    /// Panics if the component has already been taken out,
+    /// if it's spawned but not yet committed,
+    /// or if the pointer belongs to another storage.
+    pub fn take(&mut self, pointer: &Pointer<T>) -> T {
+        assert!(
+            Arc::ptr_eq(&pointer.pending, &self.pending),
+            "The pointer belongs to another storage"
+        );
+        let index = pointer.data.get_index();
+        let slot = self.inner.slots.get(index).cloned().unwrap_or(VACANT);
         {
-            let (refs, epoch) = pending.drain_sub();
-            for index in refs {
-                self.inner.meta[index] -= 1;
-                if self.inner.meta[index] == 0 {
-                    epoch[index] += 1;
-                    let data = PointerData::new(index, epoch[index], self.id);
-                    self.inner.free_list.push(data);
+            let mut epoch = self.pending.epoch.write();
+            assert_eq!(
+                epoch_at(&epoch, index),
+                pointer.data.get_epoch(),
+                "The component is already taken"
+            );
+            assert!(
+                slot != VACANT,
+                "The component is spawned but not yet committed"
+            );
+            while epoch.len() <= index {
+                epoch.push(0);
+            }
+            epoch[index] += 1;
+        }
+        let data = pointer.data;
+        let mut value = self.inner.vacate(slot);
+        self.hooks.destroyed(&self.pending, data, &mut value);
+        value
+    }
+
+    /// Drop the component in place, see [`take`](struct.Storage.html#method.take).
+    pub fn remove(&mut self, pointer: &Pointer<T>) {
+        self.take(pointer);
+    }
+
+    /// Move all the components to the front of the storage, keeping their order,
+    /// and cut off the vacant slots left behind. All the pointers stay valid.
+    ///
+    /// Components that are no longer referenced are moved as well, unless
+    /// [`sync_pending`](struct.Storage.html#method.sync_pending) is called first.
+    pub fn compact(&mut self) {
+        let order = self.occupied_slots();
+        self.inner.permute(&order);
+    }
+
+    /// Reorder the components, so that each one comes after the components it depends on,
+    /// as returned by `dependencies`. This way, [`cursor`](struct.Storage.html#method.cursor)
+    /// can always find the dependencies in the left slice. All the pointers stay valid.
+    ///
+    /// Components that are already in order keep their relative order.
+    /// The returned permutation describes the moves.
+    /// Pointers to other storages and to components that got taken out are ignored.
+    /// In a dependency cycle, one of the dependencies is going to be violated,
+    /// which can be checked with
+    /// [`dependency_violations`](struct.Storage.html#method.dependency_violations).
+    /// The vacant slots are cut off, like in [`compact`](struct.Storage.html#method.compact).
+    /// # Examples
+    /// ```rust
+    /// # use froggy::{Pointer, Storage};
+    /// struct Node {
+    ///     parent: Option<Pointer<Node>>,
+    /// }
+    /// let mut storage = Storage::new();
+    /// let child = storage.create(Node { parent: None });
+    /// let parent = storage.create(Node { parent: None });
+    /// storage[&child].parent = Some(parent.clone());
+    ///
+    /// let permutation = storage.sort_by_dependency(|node| node.parent.iter().collect());
+    /// assert_eq!(permutation.sources(), &[1, 0]);
+    /// assert!(storage.dependency_violations(|node| node.parent.iter().collect()).is_empty());
+    /// ```
+    pub fn sort_by_dependency<F>(&mut self, mut dependencies: F) -> Permutation
+    where
+        F: for<'a> FnMut(&'a T) -> Vec<&'a Pointer<T>>,
+    {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            New,
+            Active,
+            Done,
+        }
+
+        let count = self.inner.data.len();
+        let mut visits = vec![Visit::New; count];
+        let mut order = Vec::with_capacity(count);
+        // depth-first search, with the dependencies of each active slot left to visit,
+        // in reverse order
+        let mut stack = Vec::new();
+        let mut visit = |slot| {
+            let mut remaining = self.dependency_slots(slot, &mut dependencies);
+            remaining.reverse();
+            (slot, remaining)
+        };
+        for root in 0..count {
+            if self.inner.handles[root] == VACANT || visits[root] != Visit::New {
+                continue;
+            }
+            visits[root] = Visit::Active;
+            stack.push(visit(root));
+            while let Some((slot, remaining)) = stack.last_mut() {
+                match remaining.pop() {
+                    Some(next) => {
+                        // active dependencies form a cycle, which can't be satisfied
+                        if visits[next] == Visit::New {
+                            visits[next] = Visit::Active;
+                            stack.push(visit(next));
+                        }
+                    }
+                    None => {
+                        visits[*slot] = Visit::Done;
+                        order.push(*slot);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        self.reorder(order)
+    }
+
+    /// Sort the components with a key extraction function, keeping all the pointers valid.
+    /// The sort is stable, and the vacant slots are cut off, like in
+    /// [`compact`](struct.Storage.html#method.compact).
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let ptr = storage.create(3);
+    /// let _others = vec![storage.create(1), storage.create(2)];
+    /// let permutation = storage.sort_by_key(|&value| value);
+    /// assert_eq!(permutation.sources(), &[1, 2, 0]);
+    /// assert_eq!(storage[&ptr], 3);
+    /// ```
+    pub fn sort_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, mut fun: F) -> Permutation {
+        let mut order = self.occupied_slots();
+        let data = &self.inner.data;
+        order.sort_by_key(|&slot| fun(unsafe { data[slot].assume_init_ref() }));
+        self.reorder(order)
+    }
+
+    /// Sort the components with a comparator function, keeping all the pointers valid.
+    /// The sort is unstable, and the vacant slots are cut off, like in
+    /// [`compact`](struct.Storage.html#method.compact).
+    pub fn sort_unstable_by<F>(&mut self, mut compare: F) -> Permutation
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let mut order = self.occupied_slots();
+        let data = &self.inner.data;
+        order.sort_unstable_by(|&a, &b| {
+            compare(unsafe { data[a].assume_init_ref() }, unsafe {
+                data[b].assume_init_ref()
+            })
+        });
+        self.reorder(order)
+    }
+
+    fn occupied_slots(&self) -> Vec<Index> {
+        (0..self.inner.data.len())
+            .filter(|&slot| self.inner.handles[slot] != VACANT)
+            .collect()
+    }
+
+    /// Move the components to the given order of slots, reporting the permutation.
+    fn reorder(&mut self, order: Vec<Index>) -> Permutation {
+        // positions of the components in `iter_all`
+        let mut positions = vec![VACANT; self.inner.data.len()];
+        let mut count = 0;
+        for (slot, position) in positions.iter_mut().enumerate() {
+            if self.inner.handles[slot] != VACANT {
+                *position = count;
+                count += 1;
+            }
+        }
+        self.inner.permute(&order);
+        Permutation {
+            sources: order.into_iter().map(|slot| positions[slot]).collect(),
+        }
+    }
+
+    /// Find the components that come before some of their dependencies,
+    /// as returned by `dependencies`, returning the (dependent, dependency) pairs.
+    /// See [`sort_by_dependency`](struct.Storage.html#method.sort_by_dependency).
+    pub fn dependency_violations<F>(&self, mut dependencies: F) -> Vec<(Pointer<T>, Pointer<T>)>
+    where
+        F: for<'a> FnMut(&'a T) -> Vec<&'a Pointer<T>>,
+    {
+        let mut violations = Vec::new();
+        for slot in 0..self.inner.data.len() {
+            for next in self.dependency_slots(slot, &mut dependencies) {
+                if next >= slot {
+                    violations.push((self.pin_slot(slot), self.pin_slot(next)));
                 }
             }
         }
+        violations
+    }
+
+    /// Return the slots of the dependencies of the component in `slot`.
+    fn dependency_slots<F>(&self, slot: Index, dependencies: &mut F) -> Vec<Index>
+    where
+        F: for<'a> FnMut(&'a T) -> Vec<&'a Pointer<T>>,
+    {
+        if self.inner.handles[slot] == VACANT {
+            return Vec::new();
+        }
+        let value = unsafe { self.inner.data[slot].assume_init_ref() };
+        dependencies(value)
+            .into_iter()
+            .filter(|pointer| Arc::ptr_eq(&pointer.pending, &self.pending))
+            // the uncommitted spawned components have no slots
+            .filter_map(|pointer| self.inner.slots.get(pointer.data.get_index()).cloned())
+            .filter(|&next| next != VACANT)
+            .collect()
     }
 
     /// Iterate all components in this storage that are still referenced from outside.
@@ -178,11 +1011,13 @@ impl<T> Storage<T> {
     /// Information about live components is updated not for all changes, but
     /// only when you explicitly call [`sync_pending`](struct.Storage.html#method.sync_pending).
     /// It means, you can get wrong results when calling this function before updating pending.
+    /// With the `atomic-refcount` feature, this information is always current.
     #[inline]
     pub fn iter(&self) -> Iter<T> {
         Iter {
             storage: &self.inner,
             skip_lost: true,
+            min_tick: 0,
             index: 0,
         }
     }
@@ -194,6 +1029,7 @@ impl<T> Storage<T> {
         Iter {
             storage: &self.inner,
             skip_lost: false,
+            min_tick: 0,
             index: 0,
         }
     }
@@ -203,27 +1039,51 @@ impl<T> Storage<T> {
     /// Information about live components is updated not for all changes, but
     /// only when you explicitly call [`sync_pending`](struct.Storage.html#method.sync_pending).
     /// It means, you can get wrong results when calling this function before updating pending.
+    /// With the `atomic-refcount` feature, this information is always current.
     #[inline]
     pub fn iter_mut(&mut self) -> IterMut<T> {
         IterMut {
-            data: self.inner.data.iter_mut(),
-            meta: self.inner.meta.iter(),
+            data: self
+                .inner
+                .data
+                .iter_mut()
+                .zip(&mut self.inner.ticks)
+                .enumerate(),
+            meta: &self.inner.meta,
+            handles: &self.inner.handles,
+            tick: self.inner.tick,
+            skip_lost: true,
         }
     }
 
     /// Iterate all components that are stored, even if not referenced, mutably.
     /// This can be faster than the regular `iter_mut` for the lack of refcount checks.
     #[inline]
-    pub fn iter_all_mut(&mut self) -> slice::IterMut<T> {
-        self.inner.data.iter_mut()
+    pub fn iter_all_mut(&mut self) -> IterMut<T> {
+        IterMut {
+            data: self
+                .inner
+                .data
+                .iter_mut()
+                .zip(&mut self.inner.ticks)
+                .enumerate(),
+            meta: &self.inner.meta,
+            handles: &self.inner.handles,
+            tick: self.inner.tick,
+            skip_lost: false,
+        }
     }
 
     /// Pin an iterated item with a newly created `Pointer`.
     pub fn pin(&self, item: &Item<T>) -> Pointer<T> {
-        let mut pending = self.pending.lock();
-        pending.add_ref.push(item.index);
+        self.pin_slot(item.index)
+    }
+
+    fn pin_slot(&self, slot: Index) -> Pointer<T> {
+        let handle = self.inner.handles[slot];
+        self.pending.acquire(handle);
         Pointer {
-            data: PointerData::new(item.index, pending.get_epoch(item.index), self.id),
+            data: PointerData::new(handle, self.pending.get_epoch(handle)),
             pending: self.pending.clone(),
             marker: PhantomData,
         }
@@ -234,8 +1094,8 @@ impl<T> Storage<T> {
     /// left slice contains all the elements that would be iterated prior to the given one,
     /// right slice contains all the elements that would be iterated after the given one
     pub fn split(&mut self, pointer: &Pointer<T>) -> (Slice<T>, &mut T, Slice<T>) {
-        debug_assert_eq!(pointer.data.get_storage_id(), self.id);
-        self.inner.split(pointer.data)
+        let slot = self.index_slot(pointer);
+        self.inner.split(slot, &self.pending)
     }
 
     /// Produce a streaming mutable iterator over components that are still referenced.
@@ -243,13 +1103,27 @@ impl<T> Storage<T> {
     /// Information about live components is updated not for all changes, but
     /// only when you explicitly call [`sync_pending`](struct.Storage.html#method.sync_pending).
     /// It means, you can get wrong results when calling this function before updating pending.
+    /// With the `atomic-refcount` feature, this information is always current.
     #[inline]
     pub fn cursor(&mut self) -> Cursor<T> {
         Cursor {
             storage: &mut self.inner,
             pending: &self.pending,
             index: 0,
-            storage_id: self.id,
+            min_tick: 0,
+        }
+    }
+
+    /// Produce a streaming mutable iterator over the components that are still referenced,
+    /// and were modified after the given tick. See
+    /// [`iter_changed_since`](struct.Storage.html#method.iter_changed_since).
+    #[inline]
+    pub fn cursor_changed_since(&mut self, tick: u64) -> Cursor<'_, T> {
+        Cursor {
+            storage: &mut self.inner,
+            pending: &self.pending,
+            index: 0,
+            min_tick: tick.saturating_add(1),
         }
     }
 
@@ -261,28 +1135,38 @@ impl<T> Storage<T> {
             storage: &mut self.inner,
             pending: &self.pending,
             index: total,
-            storage_id: self.id,
+            min_tick: 0,
         }
     }
 
     /// Add a new component to the storage, returning the `Pointer` to it.
     pub fn create(&mut self, value: T) -> Pointer<T> {
-        let data = match self.inner.free_list.pop() {
+        let inner = &mut self.inner;
+        let slot = inner.place(value);
+        let data = match inner.free_list.pop() {
             Some(data) => {
                 let i = data.get_index();
-                debug_assert_eq!(self.inner.meta[i], 0);
-                self.inner.data[i] = value;
-                self.inner.meta[i] = 1;
+                debug_assert_eq!(inner.meta.get(i), 0);
+                debug_assert_eq!(inner.slots[i], VACANT);
+                inner.meta.set(i, 1);
+                inner.slots[i] = slot;
                 data
             }
             None => {
-                let i = self.inner.meta.len();
-                debug_assert_eq!(self.inner.data.len(), i);
-                self.inner.data.push(value);
-                self.inner.meta.push(1);
-                PointerData::new(i, 0, self.id)
+                // shared with the spawners
+                let i = self
+                    .pending
+                    .next_handle
+                    .fetch_add(1, AtomicOrdering::Relaxed);
+                inner.grow_handles(i + 1);
+                inner.meta.set(i, 1);
+                inner.slots[i] = slot;
+                PointerData::new(i, 0)
             }
         };
+        inner.handles[slot] = data.get_index();
+        let value = unsafe { inner.data[slot].assume_init_mut() };
+        self.hooks.created(&self.pending, data, value);
         Pointer {
             data,
             pending: self.pending.clone(),
@@ -291,6 +1175,73 @@ impl<T> Storage<T> {
     }
 }
 
+/// Statistics of a storage, see [`Storage::stats`](struct.Storage.html#method.stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    /// Number of slots, occupied or not.
+    pub slots: usize,
+    /// Number of components that are referenced from outside.
+    /// Without the `atomic-refcount` feature, this is as of the last
+    /// [`sync_pending`](struct.Storage.html#method.sync_pending).
+    pub live: usize,
+    /// Number of handles ready to be reused.
+    pub free_handles: usize,
+    /// Number of handles that exhausted their epochs.
+    pub retired: usize,
+    /// Number of queued reference additions. Always zero with `atomic-refcount`.
+    pub pending_adds: usize,
+    /// Number of queued reference removals. With `atomic-refcount`,
+    /// these are the components that lost their last reference.
+    pub pending_subs: usize,
+    /// Number of components queued by the spawners.
+    pub pending_spawns: usize,
+    /// Largest reference count of a component, as of the last sync
+    /// without the `atomic-refcount` feature.
+    pub max_refcount: usize,
+    /// Fraction of the slots that are vacant, from 0 to 1.
+    pub fragmentation: f32,
+}
+
+/// The reordering of the components of a storage, for side tables to follow.
+///
+/// Components are identified by their position in
+/// [`iter_all`](struct.Storage.html#method.iter_all).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Permutation {
+    sources: Vec<Index>,
+}
+
+impl Permutation {
+    /// Return the number of components.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Check if there are no components.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Return the old positions of the components, in their new order.
+    pub fn sources(&self) -> &[usize] {
+        &self.sources
+    }
+
+    /// Reorder a side table the same way as the components.
+    ///
+    /// # Panics
+    /// Panics if the table doesn't have an entry for each component.
+    pub fn apply<U>(&self, table: &mut Vec<U>) {
+        assert_eq!(table.len(), self.sources.len());
+        let mut entries: Vec<_> = table.drain(..).map(Some).collect();
+        table.extend(
+            self.sources
+                .iter()
+                .map(|&source| entries[source].take().unwrap()),
+        );
+    }
+}
+
 impl<T> Default for Storage<T> {
     fn default() -> Self {
         Self::new()
@@ -316,6 +1267,8 @@ impl<'a, T> ops::Deref for Item<'a, T> {
 pub struct Iter<'a, T: 'a> {
     storage: &'a StorageInner<T>,
     skip_lost: bool,
+    /// Skip the components that were last modified before this tick.
+    min_tick: u64,
     index: Index,
 }
 
@@ -328,9 +1281,13 @@ impl<'a, T> Iterator for Iter<'a, T> {
                 return None;
             }
             self.index += 1;
-            if !self.skip_lost || unsafe { *self.storage.meta.get_unchecked(id) } != 0 {
+            let handle = unsafe { *self.storage.handles.get_unchecked(id) };
+            if handle != VACANT
+                && (!self.skip_lost || self.storage.meta.get(handle) != 0)
+                && self.storage.ticks[id] >= self.min_tick
+            {
                 return Some(Item {
-                    value: unsafe { self.storage.data.get_unchecked(id) },
+                    value: unsafe { self.storage.data.get_unchecked(id).assume_init_ref() },
                     index: id,
                 });
             }
@@ -343,6 +1300,7 @@ impl<'a, T> Clone for Iter<'a, T> {
         Iter {
             storage: self.storage,
             skip_lost: self.skip_lost,
+            min_tick: self.min_tick,
             index: self.index,
         }
     }
@@ -351,25 +1309,36 @@ impl<'a, T> Clone for Iter<'a, T> {
 /// Iterator for writing components.
 #[derive(Debug)]
 pub struct IterMut<'a, T: 'a> {
-    data: slice::IterMut<'a, T>,
-    meta: slice::Iter<'a, RefCount>,
+    data: iter::Enumerate<iter::Zip<slice::IterMut<'a, MaybeUninit<T>>, slice::IterMut<'a, u64>>>,
+    meta: &'a Meta,
+    handles: &'a [Index],
+    tick: u64,
+    skip_lost: bool,
 }
 
 impl<'a, T> Iterator for IterMut<'a, T> {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(&0) = self.meta.next() {
-            self.data.next();
+        loop {
+            let (index, (value, tick)) = self.data.next()?;
+            let handle = self.handles[index];
+            if handle != VACANT && (!self.skip_lost || self.meta.get(handle) != 0) {
+                *tick = self.tick;
+                return Some(unsafe { value.assume_init_mut() });
+            }
         }
-        self.data.next()
     }
 }
 
 impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        while let Some(&0) = self.meta.next_back() {
-            self.data.next_back();
+        loop {
+            let (index, (value, tick)) = self.data.next_back()?;
+            let handle = self.handles[index];
+            if handle != VACANT && (!self.skip_lost || self.meta.get(handle) != 0) {
+                *tick = self.tick;
+                return Some(unsafe { value.assume_init_mut() });
+            }
         }
-        self.data.next_back()
     }
 }
@@ -0,0 +1,213 @@
+//! Consistency checks of the storage bookkeeping.
+
+use crate::{epoch_at, storage::VACANT, Storage, MAX_EPOCH};
+
+/// A broken invariant of a storage, found by
+/// [`Storage::validate`](struct.Storage.html#method.validate).
+///
+/// Slots are the positions of the components in the storage, and handles are
+/// the indices the pointers refer to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// An internal table doesn't have the length it should.
+    /// The other checks are skipped in this case.
+    LengthMismatch {
+        /// Name of the table.
+        table: &'static str,
+        /// Actual length of the table.
+        len: usize,
+        /// Length it should have.
+        expected: usize,
+    },
+    /// A slot and a handle don't refer to each other.
+    BrokenLink {
+        /// Slot of the component.
+        slot: usize,
+        /// Handle of the component.
+        handle: usize,
+    },
+    /// A vacant slot is not available for reuse exactly once,
+    /// or an occupied slot is available for reuse.
+    Hole {
+        /// Slot in question.
+        slot: usize,
+    },
+    /// A free handle is out of range.
+    UnknownHandle {
+        /// Handle in question.
+        handle: usize,
+    },
+    /// A free handle still has a component, or is still referenced.
+    LiveOnFreeList {
+        /// Handle in question.
+        handle: usize,
+    },
+    /// A handle is free more than once.
+    DuplicateFree {
+        /// Handle in question.
+        handle: usize,
+    },
+    /// A free handle is going to be reused with an epoch that doesn't match the current one,
+    /// or it has exhausted the epochs.
+    FreeEpoch {
+        /// Handle in question.
+        handle: usize,
+        /// Epoch of the free list entry.
+        epoch: usize,
+        /// Current epoch of the handle.
+        expected: usize,
+    },
+    /// A handle is not referenced, but neither free nor retired.
+    LostHandle {
+        /// Handle in question.
+        handle: usize,
+    },
+    /// The number of retired handles doesn't match the count of the storage.
+    RetiredCount {
+        /// Number of handles that exhausted their epochs.
+        count: usize,
+        /// Count of the storage.
+        expected: usize,
+    },
+    /// The storage has updates that are not synchronized.
+    PendingUpdates {
+        /// Number of queued reference additions.
+        adds: usize,
+        /// Number of queued reference removals.
+        subs: usize,
+        /// Number of queued spawned components.
+        spawns: usize,
+    },
+}
+
+impl<T> Storage<T> {
+    /// Check the consistency of the internal bookkeeping, returning the violations found.
+    ///
+    /// The storage is expected to be synchronized: any pending updates are reported,
+    /// and the checks that depend on them are skipped. Call
+    /// [`sync_pending`](struct.Storage.html#method.sync_pending) first.
+    /// This is meant for the tests, since it goes through the whole storage.
+    /// # Examples
+    /// ```rust
+    /// # let mut storage = froggy::Storage::new();
+    /// let _a = storage.create(1);
+    /// drop(storage.create(2));
+    /// assert!(!storage.validate().is_empty());
+    /// storage.sync_pending();
+    /// assert_eq!(storage.validate(), Vec::new());
+    /// ```
+    pub fn validate(&self) -> Vec<Violation> {
+        let inner = &self.inner;
+        let epoch = self.pending.epoch.read();
+        let mut violations = Vec::new();
+
+        let (slot_count, handle_count) = (inner.data.len(), inner.slots.len());
+        for &(table, len, expected) in &[
+            ("handles", inner.handles.len(), slot_count),
+            ("ticks", inner.ticks.len(), slot_count),
+            ("meta", inner.meta.len(), handle_count),
+        ] {
+            if len != expected {
+                violations.push(Violation::LengthMismatch {
+                    table,
+                    len,
+                    expected,
+                });
+            }
+        }
+        if epoch.len() > handle_count {
+            violations.push(Violation::LengthMismatch {
+                table: "epochs",
+                len: epoch.len(),
+                expected: handle_count,
+            });
+        }
+        if !violations.is_empty() {
+            return violations;
+        }
+
+        #[cfg(not(feature = "atomic-refcount"))]
+        let adds = self.pending.add_ref.len();
+        #[cfg(feature = "atomic-refcount")]
+        let adds = 0;
+        let (subs, spawns) = (self.pending.sub_ref.len(), self.spawned.len());
+        let synced = adds == 0 && subs == 0 && spawns == 0;
+        if !synced {
+            violations.push(Violation::PendingUpdates { adds, subs, spawns });
+        }
+
+        // the mapping between the slots and the handles
+        for (slot, &handle) in inner.handles.iter().enumerate() {
+            if handle != VACANT && inner.slots.get(handle) != Some(&slot) {
+                violations.push(Violation::BrokenLink { slot, handle });
+            }
+        }
+        for (handle, &slot) in inner.slots.iter().enumerate() {
+            if slot != VACANT && inner.handles.get(slot) != Some(&handle) {
+                violations.push(Violation::BrokenLink { slot, handle });
+            }
+        }
+
+        // every vacant slot is a hole, once
+        let mut holes = vec![0; slot_count];
+        for &slot in &inner.holes {
+            match holes.get_mut(slot) {
+                Some(count) => *count += 1,
+                None => violations.push(Violation::Hole { slot }),
+            }
+        }
+        for (slot, (&count, &handle)) in holes.iter().zip(&inner.handles).enumerate() {
+            if count != (handle == VACANT) as usize {
+                violations.push(Violation::Hole { slot });
+            }
+        }
+
+        let mut free = vec![false; handle_count];
+        for data in &inner.free_list {
+            let handle = data.get_index();
+            if handle >= handle_count {
+                violations.push(Violation::UnknownHandle { handle });
+                continue;
+            }
+            if free[handle] {
+                violations.push(Violation::DuplicateFree { handle });
+            }
+            free[handle] = true;
+            if inner.slots[handle] != VACANT || inner.meta.get(handle) != 0 {
+                violations.push(Violation::LiveOnFreeList { handle });
+            }
+            let expected = epoch_at(&epoch, handle);
+            if data.get_epoch() != expected || expected == MAX_EPOCH {
+                violations.push(Violation::FreeEpoch {
+                    handle,
+                    epoch: data.get_epoch() as usize,
+                    expected: expected as usize,
+                });
+            }
+        }
+
+        // Before the sync, the handles can lose their references
+        // without being freed yet. The components without references are fine,
+        // since collecting or deserializing a storage creates them.
+        if synced {
+            let mut retired = 0;
+            for (handle, &is_free) in free.iter().enumerate() {
+                if is_free || inner.meta.get(handle) != 0 || inner.slots[handle] != VACANT {
+                    continue;
+                }
+                if epoch_at(&epoch, handle) == MAX_EPOCH {
+                    retired += 1;
+                } else {
+                    violations.push(Violation::LostHandle { handle });
+                }
+            }
+            if retired != inner.retired {
+                violations.push(Violation::RetiredCount {
+                    count: retired,
+                    expected: inner.retired,
+                });
+            }
+        }
+        violations
+    }
+}
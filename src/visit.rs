@@ -0,0 +1,184 @@
+//! Visiting the pointers embedded in components.
+
+use std::{collections::VecDeque, rc::Rc, sync::Arc};
+
+use crate::{Pointer, WeakPointer};
+
+/// Receiver of the pointers found by [`PointerVisit`](trait.PointerVisit.html).
+pub trait PointerVisitor {
+    /// Visit a strong pointer.
+    fn visit_pointer<T>(&mut self, pointer: &Pointer<T>);
+    /// Visit a weak pointer. Ignored by default.
+    fn visit_weak<T>(&mut self, _pointer: &WeakPointer<T>) {}
+}
+
+/// A type that can contain pointers to components.
+///
+/// It's needed for the components to take part in the cycle collection,
+/// and can be derived with the `derive` feature.
+/// # Examples
+/// ```rust
+/// # use froggy::{Pointer, PointerVisit, PointerVisitor, Storage};
+/// struct Node {
+///     value: u32,
+///     next: Option<Pointer<Node>>,
+/// }
+/// impl PointerVisit for Node {
+///     fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+///         self.next.visit_pointers(visitor);
+///     }
+/// }
+///
+/// struct Counter(usize);
+/// impl PointerVisitor for Counter {
+///     fn visit_pointer<T>(&mut self, _pointer: &Pointer<T>) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// let mut storage = Storage::new();
+/// let ptr1 = storage.create(Node { value: 1, next: None });
+/// let ptr2 = storage.create(Node { value: 2, next: Some(ptr1.clone()) });
+/// let mut counter = Counter(0);
+/// storage[&ptr2].visit_pointers(&mut counter);
+/// assert_eq!(counter.0, 1);
+/// ```
+pub trait PointerVisit {
+    /// Pass all the pointers contained in `self` to the visitor.
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V);
+}
+
+impl<T> PointerVisit for Pointer<T> {
+    #[inline]
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        visitor.visit_pointer(self);
+    }
+}
+
+impl<T> PointerVisit for WeakPointer<T> {
+    #[inline]
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        visitor.visit_weak(self);
+    }
+}
+
+impl<U: PointerVisit> PointerVisit for Option<U> {
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        if let Some(ref value) = *self {
+            value.visit_pointers(visitor);
+        }
+    }
+}
+
+impl<U: PointerVisit> PointerVisit for [U] {
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        for value in self {
+            value.visit_pointers(visitor);
+        }
+    }
+}
+
+impl<U: PointerVisit, const N: usize> PointerVisit for [U; N] {
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        self[..].visit_pointers(visitor);
+    }
+}
+
+impl<U: PointerVisit> PointerVisit for Vec<U> {
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        self[..].visit_pointers(visitor);
+    }
+}
+
+impl<U: PointerVisit> PointerVisit for VecDeque<U> {
+    fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+        for value in self {
+            value.visit_pointers(visitor);
+        }
+    }
+}
+
+macro_rules! impl_deref {
+    ($($name:ident),*) => {
+        $(
+            impl<U: PointerVisit + ?Sized> PointerVisit for $name<U> {
+                fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+                    (**self).visit_pointers(visitor);
+                }
+            }
+        )*
+    };
+}
+
+impl_deref!(Box);
+
+// The shared values are only owned by the component if nothing else refers to them.
+macro_rules! impl_shared {
+    ($($name:ident),*) => {
+        $(
+            impl<U: PointerVisit + ?Sized> PointerVisit for $name<U> {
+                fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+                    if $name::strong_count(self) == 1 {
+                        (**self).visit_pointers(visitor);
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_shared!(Rc, Arc);
+
+macro_rules! impl_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: PointerVisit),*> PointerVisit for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+                let ($(ref $name,)*) = *self;
+                $( $name.visit_pointers(visitor); )*
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+
+macro_rules! impl_empty {
+    ($($ty:ty),*) => {
+        $(
+            impl PointerVisit for $ty {
+                #[inline]
+                fn visit_pointers<V: PointerVisitor>(&self, _visitor: &mut V) {}
+            }
+        )*
+    };
+}
+
+impl_empty!(
+    (),
+    bool,
+    char,
+    str,
+    String,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
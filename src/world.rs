@@ -0,0 +1,299 @@
+//! Registry of storages for all the component types.
+
+use std::{
+    any::{self, Any, TypeId},
+    cell::UnsafeCell,
+    collections::HashMap,
+    fmt, ops,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use crate::{Pointer, Storage};
+
+/// Borrow state of a storage that is mutably borrowed.
+const WRITER: usize = usize::MAX;
+
+/// Storage with a run-time checked borrow state, similar to `RefCell`,
+/// but shareable across threads.
+struct StorageCell<T> {
+    borrows: AtomicUsize,
+    storage: UnsafeCell<Storage<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for StorageCell<T> {}
+
+impl<T> StorageCell<T> {
+    fn borrow(&self) -> StorageRef<'_, T> {
+        let result = self
+            .borrows
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                if count < WRITER - 1 {
+                    Some(count + 1)
+                } else {
+                    None
+                }
+            });
+        if result.is_err() {
+            panic!(
+                "{} is already borrowed mutably",
+                any::type_name::<Storage<T>>()
+            );
+        }
+        StorageRef { cell: self }
+    }
+
+    fn borrow_mut(&self) -> StorageMut<'_, T> {
+        if self
+            .borrows
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            panic!("{} is already borrowed", any::type_name::<Storage<T>>());
+        }
+        StorageMut { cell: self }
+    }
+}
+
+/// Type-erased storage cell.
+trait AnyStorage: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Synchronize the pending updates, returning the number of dropped components.
+    fn sync_pending(&mut self) -> usize;
+}
+
+impl<T: Send + Sync + 'static> AnyStorage for StorageCell<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn sync_pending(&mut self) -> usize {
+        self.storage.get_mut().sync_dropped()
+    }
+}
+
+/// Shared borrow of a storage in the `World`.
+pub struct StorageRef<'a, T: 'a> {
+    cell: &'a StorageCell<T>,
+}
+
+impl<'a, T> ops::Deref for StorageRef<'a, T> {
+    type Target = Storage<T>;
+    fn deref(&self) -> &Storage<T> {
+        unsafe { &*self.cell.storage.get() }
+    }
+}
+
+impl<'a, T> Drop for StorageRef<'a, T> {
+    fn drop(&mut self) {
+        self.cell.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for StorageRef<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Exclusive borrow of a storage in the `World`.
+pub struct StorageMut<'a, T: 'a> {
+    cell: &'a StorageCell<T>,
+}
+
+impl<'a, T> ops::Deref for StorageMut<'a, T> {
+    type Target = Storage<T>;
+    fn deref(&self) -> &Storage<T> {
+        unsafe { &*self.cell.storage.get() }
+    }
+}
+
+impl<'a, T> ops::DerefMut for StorageMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Storage<T> {
+        unsafe { &mut *self.cell.storage.get() }
+    }
+}
+
+impl<'a, T> Drop for StorageMut<'a, T> {
+    fn drop(&mut self) {
+        self.cell.borrows.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for StorageMut<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// Shared borrow of a component in the `World`, keeping its storage borrowed.
+pub struct ComponentRef<'a, T: 'a> {
+    component: &'a T,
+    _storage: StorageRef<'a, T>,
+}
+
+impl<'a, T> ops::Deref for ComponentRef<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.component
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for ComponentRef<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.component.fmt(f)
+    }
+}
+
+/// Collection of storages, one per component type, created on the first use.
+///
+/// The storages can be borrowed through a shared reference to the `World`,
+/// either by many readers or by a single writer at a time, which is checked
+/// at run-time. Conflicting borrows panic instead of blocking.
+/// # Examples
+/// ```rust
+/// # use froggy::{Pointer, World};
+/// struct Position(f32);
+/// struct Velocity {
+///     value: f32,
+///     target: Pointer<Position>,
+/// }
+///
+/// let mut world = World::new();
+/// let pos = world.create(Position(0.0));
+/// let _vel = world.create(Velocity { value: 2.0, target: pos.clone() });
+/// {
+///     let velocities = world.storage::<Velocity>();
+///     let mut positions = world.storage_mut::<Position>();
+///     for vel in velocities.iter() {
+///         positions[&vel.target].0 += vel.value;
+///     }
+/// }
+/// assert_eq!(world.get(&pos).unwrap().0, 2.0);
+/// ```
+#[derive(Default)]
+pub struct World {
+    /// The cells are never shared outside of the map, but they are reference counted
+    /// to have a stable address, which the map is free to move the handles to.
+    storages: RwLock<HashMap<TypeId, Arc<dyn AnyStorage>>>,
+}
+
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("World")
+            .field("storages", &self.storages.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl World {
+    /// Create a new empty world.
+    pub fn new() -> Self {
+        World {
+            storages: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Return the cell of the storage for `T`, creating it if needed.
+    fn cell<T: Send + Sync + 'static>(&self) -> &StorageCell<T> {
+        let id = TypeId::of::<T>();
+        let existing = self.storages.read().unwrap().get(&id).map(Arc::as_ptr);
+        let cell = existing.unwrap_or_else(|| {
+            let mut storages = self.storages.write().unwrap();
+            let storage = storages.entry(id).or_insert_with(|| {
+                Arc::new(StorageCell {
+                    borrows: AtomicUsize::new(0),
+                    storage: UnsafeCell::new(Storage::<T>::new()),
+                })
+            });
+            Arc::as_ptr(storage)
+        });
+        // The cells are never removed or replaced while the world is shared,
+        // so they outlive the lock of the map.
+        unsafe { &*cell }.as_any().downcast_ref().unwrap()
+    }
+
+    /// Return the storage for `T`, if it was created.
+    fn storage_get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut Storage<T>> {
+        let cell = self
+            .storages
+            .get_mut()
+            .unwrap()
+            .get_mut(&TypeId::of::<T>())?;
+        Arc::get_mut(cell)
+            .unwrap()
+            .as_any_mut()
+            .downcast_mut::<StorageCell<T>>()
+            .map(|cell| cell.storage.get_mut())
+    }
+
+    /// Borrow the storage for `T` for reading.
+    ///
+    /// # Panics
+    /// Panics if the storage is borrowed for writing.
+    pub fn storage<T: Send + Sync + 'static>(&self) -> StorageRef<'_, T> {
+        self.cell().borrow()
+    }
+
+    /// Borrow the storage for `T` for writing.
+    ///
+    /// # Panics
+    /// Panics if the storage is borrowed.
+    pub fn storage_mut<T: Send + Sync + 'static>(&self) -> StorageMut<'_, T> {
+        self.cell().borrow_mut()
+    }
+
+    /// Create a new component in the storage for `T`.
+    pub fn create<T: Send + Sync + 'static>(&mut self, value: T) -> Pointer<T> {
+        self.storage_mut().create(value)
+    }
+
+    /// Borrow the component referenced by the pointer,
+    /// or return `None` if it's dead or doesn't belong to this world.
+    /// The storage for `T` stays borrowed for reading until the result is dropped.
+    ///
+    /// # Panics
+    /// Panics if the storage is borrowed for writing.
+    pub fn get<T: Send + Sync + 'static>(
+        &self,
+        pointer: &Pointer<T>,
+    ) -> Option<ComponentRef<'_, T>> {
+        let storage = self.storage::<T>();
+        let component = unsafe { &*storage.cell.storage.get() }.get(pointer)?;
+        Some(ComponentRef {
+            component,
+            _storage: storage,
+        })
+    }
+
+    /// Return the component referenced by the pointer mutably,
+    /// or `None` if it's dead or doesn't belong to this world.
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self, pointer: &Pointer<T>) -> Option<&mut T> {
+        self.storage_get_mut()?.get_mut(pointer)
+    }
+
+    /// Synchronize the pending updates of all the storages,
+    /// see [`Storage::sync_pending`](struct.Storage.html#method.sync_pending).
+    ///
+    /// Dropping a component may release the last pointer to a component
+    /// in another storage, so this repeats until nothing else gets dropped.
+    pub fn sync_all(&mut self) {
+        let storages = self.storages.get_mut().unwrap();
+        loop {
+            let dropped: usize = storages
+                .values_mut()
+                .map(|storage| Arc::get_mut(storage).unwrap().sync_pending())
+                .sum();
+            if dropped == 0 {
+                break;
+            }
+        }
+    }
+}
@@ -7,6 +7,8 @@ fn sizes() {
     assert_eq!(size_of::<Option<Pointer<()>>>(), 16);
 }
 
+// the components created without keeping a pointer are only dropped on sync
+#[cfg(not(feature = "atomic-refcount"))]
 #[test]
 fn change_by_pointer() {
     let mut storage = Storage::new();
@@ -54,11 +56,22 @@ fn weak_epoch() {
     };
     storage.sync_pending();
     assert_eq!(storage.iter_mut().count(), 0);
-    assert_eq!(weak.upgrade(), Err(froggy::DeadComponentError));
+    assert_eq!(weak.upgrade(), Err(froggy::Error::DeadComponent));
     let _ptr = storage.create(1 as i32);
     storage.sync_pending();
     assert_eq!(storage.iter_mut().count(), 1);
-    assert_eq!(weak.upgrade(), Err(froggy::DeadComponentError));
+    assert_eq!(weak.upgrade(), Err(froggy::Error::DeadComponent));
+}
+
+#[test]
+fn weak_dropped_storage() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1 as i32);
+    let weak = ptr.downgrade();
+    assert!(ptr.is_storage_alive());
+    drop(storage);
+    assert!(!ptr.is_storage_alive());
+    assert_eq!(weak.upgrade(), Err(froggy::Error::StorageDropped));
 }
 
 #[test]
@@ -115,6 +128,7 @@ fn storage_default() {
     storage.create(1u32);
 }
 
+#[cfg(not(feature = "atomic-refcount"))]
 #[test]
 fn pointer_eq() {
     let mut storage = Storage::new();
@@ -132,6 +146,7 @@ fn pointer_eq() {
     assert_eq!(ptr2, ptr2.clone());
 }
 
+#[cfg(not(feature = "atomic-refcount"))]
 #[test]
 fn weak_pointer_eq() {
     let mut storage = Storage::new();
@@ -157,7 +172,7 @@ fn test_send() {
     assert_send::<Storage<i32>>();
     assert_send::<Pointer<i32>>();
     assert_send::<WeakPointer<i32>>();
-    assert_send::<froggy::DeadComponentError>();
+    assert_send::<froggy::Error>();
 }
 
 #[test]
@@ -166,7 +181,7 @@ fn test_sync() {
     assert_sync::<Storage<i32>>();
     assert_sync::<Pointer<i32>>();
     assert_sync::<WeakPointer<i32>>();
-    assert_sync::<froggy::DeadComponentError>();
+    assert_sync::<froggy::Error>();
 }
 
 #[test]
@@ -178,3 +193,1216 @@ fn test_hash() {
     hash_map.insert(ptr.clone(), 23u8);
     assert_eq!(hash_map.get(&ptr), Some(&23u8));
 }
+
+#[test]
+fn drop_on_sync() {
+    use std::{cell::Cell, rc::Rc};
+    struct Tracker(Rc<Cell<usize>>);
+    impl Drop for Tracker {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    let drops = Rc::new(Cell::new(0));
+    let mut storage = Storage::new();
+    let ptr = storage.create(Tracker(drops.clone()));
+    let _other = storage.create(Tracker(drops.clone()));
+    drop(ptr);
+    assert_eq!(drops.get(), 0);
+    storage.sync_pending();
+    assert_eq!(drops.get(), 1);
+    assert_eq!(storage.iter_all().count(), 1);
+    assert_eq!(storage.iter_all_mut().count(), 1);
+    storage.create(Tracker(drops.clone()));
+    assert_eq!(drops.get(), 1);
+    drop(storage);
+    assert_eq!(drops.get(), 3);
+}
+
+#[test]
+fn drop_chain_on_sync() {
+    struct Node {
+        _parent: Option<Pointer<Node>>,
+    }
+    let mut storage = Storage::new();
+    let root = storage.create(Node { _parent: None });
+    let child = storage.create(Node {
+        _parent: Some(root.clone()),
+    });
+    let leaf = storage.create(Node {
+        _parent: Some(child.clone()),
+    });
+    drop((root, child, leaf));
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+}
+
+#[test]
+fn take() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    let ptr2 = ptr.clone();
+    let weak = ptr.downgrade();
+    let _other = storage.create(2u32);
+    assert_eq!(storage.take(&ptr), 1);
+    assert!(!storage.contains(&ptr2));
+    assert!(weak.upgrade().is_err());
+    assert_eq!(storage.iter_all().count(), 1);
+    storage.sync_pending();
+    assert_eq!(storage.iter().count(), 1);
+    assert_eq!(storage.iter_mut().count(), 1);
+    // the slot is not reused while stale pointers are around
+    let ptr3 = storage.create(3u32);
+    assert_ne!(ptr3.partial_cmp(&ptr), Some(std::cmp::Ordering::Equal));
+    drop((ptr, ptr2));
+    storage.sync_pending();
+    let ptr4 = storage.create(4u32);
+    assert!(storage.contains(&ptr4));
+    assert!(weak.upgrade().is_err());
+    assert_eq!(storage[&ptr4], 4);
+}
+
+#[test]
+fn remove() {
+    use std::rc::Rc;
+    let value = Rc::new(());
+    let mut storage = Storage::new();
+    let ptr = storage.create(value.clone());
+    storage.remove(&ptr);
+    assert_eq!(Rc::strong_count(&value), 1);
+    assert!(!storage.contains(&ptr));
+    let mut cursor = storage.cursor();
+    assert!(cursor.next().is_none());
+}
+
+#[test]
+#[should_panic]
+fn take_twice() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    storage.take(&ptr);
+    storage.take(&ptr);
+}
+
+#[test]
+#[should_panic(expected = "not yet committed")]
+fn take_uncommitted() {
+    let mut storage = Storage::new();
+    let spawned = storage.spawner().spawn(1u32);
+    storage.take(&spawned);
+}
+
+#[test]
+#[should_panic(expected = "Invalid pointer")]
+fn index_taken() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    storage.take(&ptr);
+    let _ = storage[&ptr];
+}
+
+#[test]
+fn checked_get() {
+    let mut storage = Storage::new();
+    let mut other = Storage::new();
+    let ptr = storage.create(1u32);
+    let foreign = other.create(2u32);
+    assert_eq!(storage.get(&ptr), Some(&1));
+    assert_eq!(storage.get(&foreign), None);
+    *storage.get_mut(&ptr).unwrap() = 5;
+    assert_eq!(storage[&ptr], 5);
+    storage.take(&ptr);
+    assert_eq!(storage.get(&ptr), None);
+    assert_eq!(storage.get_mut(&ptr), None);
+}
+
+#[cfg(feature = "checked-index")]
+#[test]
+#[should_panic]
+fn checked_index() {
+    let mut storage = Storage::new();
+    let mut other = Storage::new();
+    let _ptr = storage.create(1u32);
+    let foreign = other.create(2u32);
+    let _ = storage[&foreign];
+}
+
+#[test]
+fn many_storages() {
+    let mut first = Storage::new();
+    let ptr = first.create(0usize);
+    for i in 1..1000 {
+        let mut storage = Storage::new();
+        let other = storage.create(i);
+        assert_eq!(storage.get(&ptr), None);
+        assert_eq!(first.get(&other), None);
+        assert_eq!(ptr.partial_cmp(&other), None);
+        assert_ne!(ptr, other);
+    }
+    let storages: Vec<_> = (0..300).map(|_| Storage::<u8>::new()).collect();
+    let pointers: Vec<_> = storages
+        .into_iter()
+        .map(|mut storage| (storage.create(0), storage))
+        .collect();
+    for (i, (ptr, storage)) in pointers.iter().enumerate() {
+        assert!(storage.contains(ptr));
+        for (other, _) in &pointers[i + 1..] {
+            assert!(!storage.contains(other));
+            assert_eq!(ptr.partial_cmp(other), None);
+        }
+    }
+}
+
+#[cfg(not(any(feature = "refcount-u32", feature = "saturating-refcount")))]
+#[test]
+#[should_panic(expected = "Reference count overflow")]
+fn refcount_overflow() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    let _clones: Vec<_> = (0..u16::MAX).map(|_| ptr.clone()).collect();
+    storage.sync_pending();
+}
+
+#[cfg(all(not(feature = "refcount-u32"), feature = "saturating-refcount"))]
+#[test]
+fn refcount_saturation() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    let clones: Vec<_> = (0..u16::MAX).map(|_| ptr.clone()).collect();
+    storage.sync_pending();
+    let weak = ptr.downgrade();
+    drop((ptr, clones));
+    storage.sync_pending();
+    assert_eq!(storage.iter().count(), 1);
+    assert!(weak.upgrade().is_ok());
+}
+
+#[test]
+fn refcount_churn() {
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    for _ in 0..70_000 {
+        drop(ptr.clone());
+    }
+    storage.sync_pending();
+    assert_eq!(storage.iter().count(), 1);
+    drop(ptr);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+}
+
+#[test]
+fn try_accessors() {
+    use froggy::Error;
+
+    let mut storage = Storage::new();
+    let mut other = Storage::new();
+    let a = storage.create(1);
+    let b = storage.create(2);
+    let c = storage.create(3);
+    let stranger = other.create(4);
+    assert_eq!(storage.try_get(&a), Ok(&1));
+    *storage.try_get_mut(&b).unwrap() += 10;
+    assert_eq!(storage.try_get(&stranger), Err(Error::WrongStorage));
+    let spawned = storage.spawner().spawn(5);
+    assert_eq!(storage.try_get(&spawned), Err(Error::Uncommitted));
+    // the handles grow past the spawned component, which is still not committed
+    let _d = storage.create(6);
+    assert_eq!(storage.try_get(&spawned), Err(Error::Uncommitted));
+    storage.remove(&c);
+    assert_eq!(storage.try_get_mut(&c), Err(Error::DeadComponent));
+    assert_eq!(Error::DeadComponent.to_string(), "the component is dead");
+
+    let (left, _, mut right) = storage.split(&b);
+    assert_eq!(left.try_get(&a), Ok(&1));
+    assert_eq!(right.try_get(&a), Err(Error::OutOfBounds));
+    assert_eq!(right.try_get_mut(&c), Err(Error::DeadComponent));
+    assert_eq!(left.try_get(&stranger), Err(Error::WrongStorage));
+    assert_eq!(left.try_get(&spawned), Err(Error::Uncommitted));
+    let boxed: Box<dyn std::error::Error> = Box::new(Error::OutOfBounds);
+    assert_eq!(boxed.to_string(), "the component is out of bounds");
+}
+
+#[cfg(not(feature = "epoch-u32"))]
+#[test]
+fn epoch_exhaustion() {
+    let mut storage = Storage::new();
+    let first = storage.create(0u32).downgrade();
+    storage.sync_pending();
+    let mut generations = 1;
+    while storage.retired_count() == 0 {
+        let ptr = storage.create(generations);
+        assert!(first.upgrade().is_err());
+        drop(ptr);
+        storage.sync_pending();
+        generations += 1;
+        assert!(generations <= 1 << 16);
+    }
+    assert_eq!(first.upgrade(), Err(froggy::Error::EpochExhausted));
+    let ptr = storage.create(0);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 1);
+    assert_eq!(storage.retired_count(), 1);
+    assert!(storage.contains(&ptr));
+    assert_eq!(first.upgrade(), Err(froggy::Error::EpochExhausted));
+    assert_eq!(storage.validate(), Vec::new());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_iter() {
+    use rayon::prelude::*;
+    let mut storage = Storage::new();
+    let pointers: Vec<_> = (0..1000u32).map(|i| storage.create(i)).collect();
+    let _zombie = storage.create(0).downgrade();
+    storage.sync_pending();
+    assert_eq!(storage.par_iter().count(), 1000);
+    assert_eq!(storage.par_iter().sum::<u32>(), 999 * 500);
+    storage.par_iter_mut().for_each(|value| *value *= 2);
+    assert_eq!(storage[&pointers[7]], 14);
+    storage.par_iter_all_mut().for_each(|value| *value += 1);
+    assert_eq!(storage[&pointers[7]], 15);
+    assert_eq!(storage.par_iter_all_mut().count(), 1000);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_for_each_with_pointer() {
+    use std::sync::Mutex;
+    let mut storage = Storage::new();
+    let pointers: Vec<_> = (0..100u32).map(|i| storage.create(i)).collect();
+    let odd = Mutex::new(Vec::new());
+    storage.par_for_each_with_pointer(|mut item| {
+        *item += 1;
+        if *item % 2 == 1 {
+            odd.lock().unwrap().push(item.pin());
+        }
+    });
+    let odd = odd.into_inner().unwrap();
+    assert_eq!(odd.len(), 50);
+    assert!(odd.iter().all(|ptr| storage[ptr] % 2 == 1));
+    assert!(odd.contains(&pointers[0]));
+}
+
+#[test]
+fn threaded_churn() {
+    use std::thread;
+    let mut storage = Storage::new();
+    let pointers: Vec<_> = (0..8u32).map(|i| storage.create(i)).collect();
+    let weak = pointers[0].downgrade();
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    let clones = pointers.to_vec();
+                    let upgraded = weak.upgrade().unwrap();
+                    drop((clones, upgraded));
+                }
+            });
+        }
+    });
+    storage.sync_pending();
+    assert_eq!(storage.iter().count(), 8);
+    drop(pointers);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+    assert!(weak.upgrade().is_err());
+}
+
+#[cfg(feature = "atomic-refcount")]
+#[test]
+fn atomic_liveness() {
+    let mut storage = Storage::new();
+    let ptr1 = storage.create(1u32);
+    let ptr2 = storage.create(2u32);
+    let ptr3 = ptr2.clone();
+    drop(ptr1);
+    // no need to sync before iterating
+    assert_eq!(
+        storage.iter().map(|item| *item).collect::<Vec<_>>(),
+        vec![2]
+    );
+    assert_eq!(storage.cursor().next().map(|(_, item, _)| *item), Some(2));
+    drop(ptr2);
+    assert_eq!(storage.iter().count(), 1);
+    drop(ptr3);
+    assert_eq!(storage.iter().count(), 0);
+    assert_eq!(storage.iter_all().count(), 2);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+}
+
+#[cfg(feature = "atomic-refcount")]
+#[test]
+fn atomic_pin() {
+    let mut storage = Storage::new();
+    let p1 = storage.create(1u32);
+    let p2 = storage.create(2u32);
+    let ptr1 = storage.pin(&storage.iter().next().unwrap());
+    let ptr2 = storage.pin(&storage.iter().nth(1).unwrap());
+    assert_eq!((&ptr1, &ptr2), (&p1, &p2));
+    assert_eq!(ptr1.downgrade(), p1.downgrade());
+    storage[&ptr2] = 350;
+    drop((p1, p2));
+    // the pinned pointers keep the components alive
+    assert_eq!(
+        storage.iter().map(|item| *item).collect::<Vec<_>>(),
+        vec![1, 350]
+    );
+    // a component without pointers is dead right away
+    storage.create(3u32);
+    assert_eq!(storage.iter().count(), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_graph() {
+    use froggy::{Error, Registry};
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Node {
+        value: u32,
+        parent: Option<Pointer<Node>>,
+    }
+
+    let mut storage = Storage::new();
+    let root = storage.create(Node {
+        value: 0,
+        parent: None,
+    });
+    let gone = storage.create(Node {
+        value: 10,
+        parent: None,
+    });
+    let weak_gone = gone.downgrade();
+    drop(gone);
+    let children: Vec<_> = (1..4)
+        .map(|value| {
+            storage.create(Node {
+                value,
+                parent: Some(root.clone()),
+            })
+        })
+        .collect();
+    storage.sync_pending();
+    let json = serde_json::to_string(&(&storage, &root, &children, &weak_gone)).unwrap();
+    drop((storage, root, children, weak_gone));
+
+    let (mut storage, root, children, weak_gone): (
+        Storage<Node>,
+        Pointer<Node>,
+        Vec<Pointer<Node>>,
+        WeakPointer<Node>,
+    ) = Registry::new()
+        .scope(|| serde_json::from_str(&json))
+        .unwrap();
+    assert_eq!(storage.iter().count(), 4);
+    for (child, value) in children.iter().zip(1..) {
+        assert_eq!(storage[child].value, value);
+        assert_eq!(storage[child].parent.as_ref(), Some(&root));
+    }
+    assert_eq!(weak_gone.upgrade(), Err(Error::DeadComponent));
+    // the vacant slot is reused
+    let ptr = storage.create(Node {
+        value: 5,
+        parent: None,
+    });
+    assert_eq!(storage.iter_all().count(), 5);
+    drop(ptr);
+    // the references are not lost or duplicated
+    drop(children);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 1);
+    drop(root);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_invalid_pointers() {
+    use froggy::Registry;
+
+    #[derive(serde::Deserialize)]
+    struct Node {
+        _parent: Option<Pointer<Node>>,
+    }
+    type Input = (Storage<Node>, Vec<Pointer<Node>>);
+    let parse = |json: &str| Registry::new().scope(|| serde_json::from_str::<Input>(json));
+
+    let valid = r#"[{"refcounts":[2,1],"epochs":[0,0],
+        "data":[{"_parent":null},{"_parent":[0,0]}]}, [[0,0],[1,0]]]"#;
+    let (storage, pointers) = parse(valid).unwrap();
+    assert_eq!(storage[&pointers[1]]._parent.as_ref(), Some(&pointers[0]));
+    // the epoch doesn't match
+    assert!(parse(
+        r#"[{"refcounts":[2,1],"epochs":[1,0],
+        "data":[{"_parent":null},{"_parent":[0,0]}]}, []]"#
+    )
+    .is_err());
+    // the component is not there
+    assert!(parse(
+        r#"[{"refcounts":[2,1],"epochs":[0,0],
+        "data":[null,{"_parent":[0,0]}]}, []]"#
+    )
+    .is_err());
+    // more pointers than references, within the data or after it
+    assert!(parse(
+        r#"[{"refcounts":[1,1,1],"epochs":[0,0,0],
+        "data":[{"_parent":null},{"_parent":[0,0]},{"_parent":[0,0]}]}, []]"#
+    )
+    .is_err());
+    assert!(parse(
+        r#"[{"refcounts":[1,1],"epochs":[0,0],
+        "data":[{"_parent":null},{"_parent":[0,0]}]}, [[0,0]]]"#
+    )
+    .is_err());
+    // the component is at the epoch of a retired handle
+    let max_epoch = if cfg!(feature = "epoch-u32") {
+        u32::MAX
+    } else {
+        u32::from(u16::MAX)
+    };
+    assert!(parse(&format!(
+        r#"[{{"refcounts":[1],"epochs":[{0}],"data":[{{"_parent":null}}]}}, [[0,{0}]]]"#,
+        max_epoch
+    ))
+    .is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_registry() {
+    use froggy::Registry;
+
+    let mut storage = Storage::new();
+    let ptr = storage.create(1u32);
+    let json = serde_json::to_string(&ptr).unwrap();
+    // there is no storage to refer to
+    assert!(serde_json::from_str::<Pointer<u32>>(&json).is_err());
+    assert!(serde_json::from_str::<Pointer<u32>>("[5,0]").is_err());
+
+    let mut registry = Registry::new();
+    registry.register(&storage);
+    let ptr2: Pointer<u32> = registry.scope(|| serde_json::from_str(&json)).unwrap();
+    assert_eq!(ptr, ptr2);
+    // deserialized pointers into existing storages hold their own references
+    drop(ptr);
+    storage.sync_pending();
+    assert_eq!(storage[&ptr2], 1);
+    drop(ptr2);
+    storage.sync_pending();
+    assert_eq!(storage.iter_all().count(), 0);
+    assert!(registry
+        .scope(|| serde_json::from_str::<Pointer<u32>>(&json))
+        .is_err());
+}
+
+#[test]
+fn compact() {
+    let mut storage = Storage::new();
+    let ptrs: Vec<_> = (0..10).map(|i| storage.create(i)).collect();
+    let weak_dead = ptrs[2].downgrade();
+    let weak_alive = ptrs[7].downgrade();
+    let (keep, gone): (Vec<_>, Vec<_>) = ptrs.into_iter().partition(|p| storage[p] % 3 == 1);
+    drop(gone);
+    storage.sync_pending();
+    storage.compact();
+    assert_eq!(
+        storage.iter_all().map(|item| *item).collect::<Vec<_>>(),
+        vec![1, 4, 7]
+    );
+    for (ptr, value) in keep.iter().zip(&[1, 4, 7]) {
+        assert_eq!(storage[ptr], *value);
+    }
+    // the components are at the front
+    assert!(storage.split(&keep[0]).0.is_empty());
+    assert!(storage.split(&keep[2]).2.is_empty());
+    assert_eq!(weak_dead.upgrade(), Err(froggy::Error::DeadComponent));
+    assert_eq!(storage[&weak_alive.upgrade().unwrap()], 7);
+    // taken components don't take up space either
+    let taken = storage.take(&keep[1]);
+    assert_eq!(taken, 4);
+    storage.compact();
+    assert!(storage.split(&keep[2]).0.get(&keep[0]).is_some());
+    assert!(!storage.contains(&keep[1]));
+    let ptr = storage.create(10);
+    assert_eq!(
+        storage.iter_all().map(|item| *item).collect::<Vec<_>>(),
+        vec![1, 7, 10]
+    );
+    assert_eq!(storage[&ptr], 10);
+}
+
+#[test]
+fn sort_by_dependency() {
+    struct Node {
+        value: u32,
+        parents: Vec<Pointer<Node>>,
+    }
+    fn parents(node: &Node) -> Vec<&Pointer<Node>> {
+        node.parents.iter().collect()
+    }
+
+    let mut storage = Storage::new();
+    let leaf = storage.create(Node {
+        value: 3,
+        parents: Vec::new(),
+    });
+    let middle = storage.create(Node {
+        value: 2,
+        parents: Vec::new(),
+    });
+    let root = storage.create(Node {
+        value: 1,
+        parents: Vec::new(),
+    });
+    let other = storage.create(Node {
+        value: 4,
+        parents: Vec::new(),
+    });
+    storage[&leaf].parents = vec![middle.clone(), root.clone()];
+    storage[&middle].parents = vec![root.clone()];
+    let violations = storage.dependency_violations(parents);
+    assert_eq!(violations.len(), 3);
+    assert_eq!(violations[0], (leaf.clone(), middle.clone()));
+
+    storage.sort_by_dependency(parents);
+    assert!(storage.dependency_violations(parents).is_empty());
+    let values: Vec<_> = storage.iter().map(|node| node.value).collect();
+    assert_eq!(values, vec![1, 2, 3, 4]);
+    assert_eq!(storage[&leaf].value, 3);
+    let mut cursor = storage.cursor();
+    while let Some((left, item, _)) = cursor.next() {
+        for parent in &item.parents {
+            assert!(left.get(parent).is_some());
+        }
+    }
+
+    // cycles can't be sorted, but are reported
+    storage[&root].parents = vec![other.clone()];
+    storage[&other].parents = vec![leaf.clone()];
+    storage.sort_by_dependency(parents);
+    assert!(!storage.dependency_violations(parents).is_empty());
+    assert_eq!(storage.iter().count(), 4);
+}
+
+#[test]
+fn sort_by_key() {
+    let mut storage = Storage::new();
+    let ptrs: Vec<_> = [5, 3, 8, 1, 3].iter().map(|&i| storage.create(i)).collect();
+    drop(storage.create(0));
+    storage.sync_pending();
+    let mut names: Vec<_> = storage
+        .iter_all()
+        .map(|item| format!("n{}", *item))
+        .collect();
+
+    let permutation = storage.sort_by_key(|&value| value);
+    assert_eq!(permutation.sources(), &[3, 1, 4, 0, 2]);
+    permutation.apply(&mut names);
+    assert_eq!(names, vec!["n1", "n3", "n3", "n5", "n8"]);
+    let values: Vec<_> = storage.iter_all().map(|item| *item).collect();
+    assert_eq!(values, vec![1, 3, 3, 5, 8]);
+    for (ptr, &value) in ptrs.iter().zip(&[5, 3, 8, 1, 3]) {
+        assert_eq!(storage[ptr], value);
+    }
+
+    let permutation = storage.sort_unstable_by(|a, b| b.cmp(a));
+    assert_eq!(permutation.len(), 5);
+    let values: Vec<_> = storage.iter_all().map(|item| *item).collect();
+    assert_eq!(values, vec![8, 5, 3, 3, 1]);
+    assert_eq!(storage[&ptrs[0]], 5);
+}
+
+#[test]
+fn collect_cycles() {
+    use froggy::{PointerVisit, PointerVisitor};
+    use std::{cell::Cell, rc::Rc, sync::Arc};
+
+    struct Node {
+        next: Vec<Pointer<Node>>,
+        drops: Rc<Cell<usize>>,
+    }
+    impl PointerVisit for Node {
+        fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+            self.next.visit_pointers(visitor);
+        }
+    }
+    impl Drop for Node {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    let drops = Rc::new(Cell::new(0));
+    let mut storage = Storage::new();
+    let node = || Node {
+        next: Vec::new(),
+        drops: drops.clone(),
+    };
+    // an unreachable cycle, with a component hanging off it
+    let a = storage.create(node());
+    let b = storage.create(node());
+    let c = storage.create(node());
+    storage[&a].next = vec![b.clone(), c.clone()];
+    storage[&b].next = vec![a.clone()];
+    // a cycle referenced from outside
+    let d = storage.create(node());
+    let e = storage.create(node());
+    storage[&d].next = vec![e.clone()];
+    storage[&e].next = vec![d.clone()];
+    let weak_a = a.downgrade();
+    drop((a, b, c, e));
+
+    assert_eq!(storage.collect_cycles(), 3);
+    assert_eq!(drops.get(), 3);
+    assert!(weak_a.upgrade().is_err());
+    assert_eq!(storage.iter().count(), 2);
+    assert_eq!(storage[&d].next.len(), 1);
+
+    // nothing else to collect, until the outside reference is gone
+    assert_eq!(storage.collect_cycles(), 0);
+    drop(d);
+    assert_eq!(storage.collect_cycles(), 2);
+    assert_eq!(drops.get(), 5);
+    assert_eq!(storage.iter_all().count(), 0);
+
+    // a cycle through a shared pointer, which is also referenced from outside
+    struct Shared {
+        next: Option<Arc<Pointer<Shared>>>,
+    }
+    impl PointerVisit for Shared {
+        fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+            self.next.visit_pointers(visitor);
+        }
+    }
+    let mut storage = Storage::new();
+    let f = storage.create(Shared { next: None });
+    let g = storage.create(Shared {
+        next: Some(Arc::new(f.clone())),
+    });
+    let outside = Arc::new(g.clone());
+    storage[&f].next = Some(outside.clone());
+    drop((f, g));
+    assert_eq!(storage.collect_cycles(), 0);
+    assert!(storage.contains(&outside));
+    drop(outside);
+    assert_eq!(storage.collect_cycles(), 2);
+}
+
+#[test]
+fn collect_cycles_across_storages() {
+    use froggy::{CycleCollector, PointerVisit, PointerVisitor};
+
+    struct Parent {
+        children: Vec<Pointer<Child>>,
+    }
+    impl PointerVisit for Parent {
+        fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+            self.children.visit_pointers(visitor);
+        }
+    }
+    struct Child {
+        parent: Option<Pointer<Parent>>,
+    }
+    impl PointerVisit for Child {
+        fn visit_pointers<V: PointerVisitor>(&self, visitor: &mut V) {
+            self.parent.visit_pointers(visitor);
+        }
+    }
+
+    let mut parents = Storage::new();
+    let mut children = Storage::new();
+    let kept = parents.create(Parent {
+        children: Vec::new(),
+    });
+    for parent in &[
+        kept.clone(),
+        parents.create(Parent {
+            children: Vec::new(),
+        }),
+    ] {
+        for _ in 0..2 {
+            let child = children.create(Child {
+                parent: Some(parent.clone()),
+            });
+            parents[parent].children.push(child);
+        }
+    }
+
+    // a single storage doesn't see the cycles
+    assert_eq!(parents.collect_cycles(), 0);
+    {
+        let mut collector = CycleCollector::new();
+        collector.add(&mut parents).add(&mut children);
+        assert_eq!(collector.collect(), 3);
+    }
+    assert_eq!(parents.iter().count(), 1);
+    assert_eq!(children.iter().count(), 2);
+    assert_eq!(parents[&kept].children.len(), 2);
+}
+
+#[test]
+fn world() {
+    use froggy::World;
+
+    struct Position(i32);
+    struct Velocity {
+        value: i32,
+        target: Pointer<Position>,
+    }
+
+    let mut world = World::new();
+    let pos = world.create(Position(1));
+    let vel = world.create(Velocity {
+        value: 5,
+        target: pos.clone(),
+    });
+    {
+        let velocities = world.storage::<Velocity>();
+        let velocities2 = world.storage::<Velocity>();
+        let mut positions = world.storage_mut::<Position>();
+        for v in velocities.iter().chain(velocities2.iter()) {
+            positions[&v.target].0 += v.value;
+        }
+    }
+    assert_eq!(world.get(&pos).unwrap().0, 11);
+    world.get_mut(&vel).unwrap().value = 0;
+    assert_eq!(world.storage::<Velocity>()[&vel].value, 0);
+    // a pointer from another storage is not found
+    let other = Storage::new().create(Position(0));
+    assert!(world.get(&other).is_none());
+
+    let weak = pos.downgrade();
+    drop((pos, vel));
+    world.sync_all();
+    assert!(weak.upgrade().is_err());
+    assert_eq!(world.storage::<Position>().iter_all().count(), 0);
+    assert_eq!(world.storage::<String>().iter_all().count(), 0);
+}
+
+#[test]
+fn world_shared_get() {
+    let world = froggy::World::new();
+    let ptr = world.storage_mut::<u8>().create(1);
+    let component = world.get(&ptr).unwrap();
+    // creating the other storages doesn't move the borrowed one
+    let _ = (
+        world.storage::<u16>(),
+        world.storage::<u32>(),
+        world.storage::<u64>(),
+    );
+    let _ = (
+        world.storage::<i8>(),
+        world.storage::<i16>(),
+        world.storage::<i32>(),
+    );
+    let _ = (
+        world.storage::<f32>(),
+        world.storage::<f64>(),
+        world.storage::<String>(),
+    );
+    assert_eq!(*component, 1);
+    assert_eq!(world.storage::<u8>()[&ptr], 1);
+    drop(component);
+    world.storage_mut::<u8>()[&ptr] = 2;
+    assert_eq!(*world.get(&ptr).unwrap(), 2);
+}
+
+#[test]
+#[should_panic]
+fn world_conflicting_borrows() {
+    let world = froggy::World::new();
+    let _positions = world.storage::<i32>();
+    world.storage_mut::<i32>();
+}
+
+#[test]
+fn scheduler() {
+    use froggy::{Access, Scheduler, System, World};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    struct Local(i32);
+    struct Node {
+        local: Pointer<Local>,
+        parent: Option<Pointer<Node>>,
+        world: i32,
+    }
+    struct Instance {
+        node: Pointer<Node>,
+        value: i32,
+    }
+
+    /// Recompute the world values of the nodes, parents first.
+    struct WorldSpaces;
+    impl System for WorldSpaces {
+        fn access(&self) -> Access {
+            Access::new().read::<Local>().write::<Node>()
+        }
+        fn run(&mut self, world: &World) {
+            let locals = world.storage::<Local>();
+            let mut nodes = world.storage_mut::<Node>();
+            let mut cursor = nodes.cursor();
+            while let Some((left, mut item, _)) = cursor.next() {
+                let parent = item
+                    .parent
+                    .as_ref()
+                    .map_or(0, |p| left.get(p).unwrap().world);
+                item.world = parent + locals[&item.local].0;
+            }
+        }
+    }
+
+    let mut world = World::new();
+    let root_local = world.create(Local(1));
+    let child_local = world.create(Local(10));
+    let root = world.create(Node {
+        local: root_local.clone(),
+        parent: None,
+        world: 0,
+    });
+    let child = world.create(Node {
+        local: child_local,
+        parent: Some(root.clone()),
+        world: 0,
+    });
+    let instance = world.create(Instance {
+        node: child.clone(),
+        value: 0,
+    });
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let animated = counter.clone();
+    let mut scheduler = Scheduler::new();
+    scheduler
+        .add_fn(Access::new().write::<Local>(), move |world| {
+            animated.fetch_add(1, Ordering::Relaxed);
+            for local in world.storage_mut::<Local>().iter_mut() {
+                local.0 += 1;
+            }
+        })
+        .add(WorldSpaces)
+        .add_fn(Access::new().read::<Node>().write::<Instance>(), |world| {
+            let nodes = world.storage::<Node>();
+            for instance in world.storage_mut::<Instance>().iter_mut() {
+                instance.value = nodes[&instance.node].world;
+            }
+        })
+        // doesn't conflict with anything, so it runs in the first stage
+        .add_fn(Access::new().read::<String>(), |world| {
+            assert_eq!(world.storage::<String>().iter().count(), 0);
+        });
+    assert_eq!(scheduler.stage_count(), 3);
+
+    scheduler.run(&mut world);
+    assert_eq!(world.get(&instance).unwrap().value, 13);
+    scheduler.run(&mut world);
+    assert_eq!(world.get(&instance).unwrap().value, 15);
+    assert_eq!(counter.load(Ordering::Relaxed), 2);
+
+    // the storages get synchronized between the stages
+    drop((root, child, root_local, instance));
+    scheduler
+        .add_barrier()
+        .add_fn(Access::new().read::<Node>(), |world| {
+            assert_eq!(world.storage::<Node>().iter().count(), 0);
+        });
+    assert_eq!(scheduler.stage_count(), 4);
+    scheduler.run(&mut world);
+    assert_eq!(world.storage::<Local>().iter_all().count(), 0);
+}
+
+#[test]
+fn lifecycle_hooks() {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    // mirror of the components, like a physics world would keep
+    let mirror = Arc::new(Mutex::new(HashMap::new()));
+    let mut storage = Storage::new();
+    let created = mirror.clone();
+    storage.on_create(move |weak: &WeakPointer<i32>, value: &mut i32| {
+        *value += 1;
+        created.lock().unwrap().insert(weak.clone(), *value);
+    });
+    let destroyed = mirror.clone();
+    storage.on_destroy(move |weak, value| {
+        assert_eq!(destroyed.lock().unwrap().remove(weak), Some(*value));
+        assert!(weak.upgrade().is_err());
+    });
+
+    let a = storage.create(1);
+    let b = storage.create(10);
+    let c = storage.create(100);
+    assert_eq!(storage[&a], 2);
+    assert_eq!(mirror.lock().unwrap()[&b.downgrade()], 11);
+    assert_eq!(mirror.lock().unwrap().len(), 3);
+
+    drop(a);
+    storage.sync_pending();
+    assert_eq!(mirror.lock().unwrap().len(), 2);
+    assert_eq!(storage.take(&b), 11);
+    assert_eq!(mirror.lock().unwrap().len(), 1);
+    // a reused slot is reported as a new component
+    let d = storage.create(1000);
+    assert_eq!(mirror.lock().unwrap().len(), 2);
+    drop((c, d));
+    storage.sync_pending();
+    assert!(mirror.lock().unwrap().is_empty());
+}
+
+#[test]
+fn change_detection() {
+    let mut storage = Storage::new();
+    let ptrs: Vec<_> = (0..5).map(|i| storage.create(i)).collect();
+    let changed = |storage: &Storage<i32>, tick| -> Vec<i32> {
+        storage.iter_changed_since(tick).map(|item| *item).collect()
+    };
+    assert_eq!(changed(&storage, 0).len(), 5);
+
+    let start = storage.current_tick();
+    assert_eq!(storage.advance_tick(), start + 1);
+    assert!(changed(&storage, start).is_empty());
+    storage[&ptrs[1]] += 10;
+    *storage.get_mut(&ptrs[3]).unwrap() += 10;
+    assert_eq!(changed(&storage, start), vec![11, 13]);
+
+    // reordering keeps the ticks with the components
+    storage.sort_by_key(|&value| -value);
+    assert_eq!(changed(&storage, start), vec![13, 11]);
+
+    let second = storage.advance_tick();
+    {
+        let mut cursor = storage.cursor_changed_since(start);
+        let mut count = 0;
+        while let Some((_, mut item, _)) = cursor.next() {
+            *item += 100;
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
+    assert_eq!(changed(&storage, second - 1), vec![113, 111]);
+    assert!(changed(&storage, second).is_empty());
+
+    storage.advance_tick();
+    for value in storage.iter_mut() {
+        *value += 1;
+    }
+    assert_eq!(changed(&storage, second).len(), 5);
+    // new components are changed as well
+    let _p = storage.create(-1);
+    assert_eq!(changed(&storage, second).len(), 6);
+}
+
+#[test]
+fn spawner() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    let mut storage = Storage::new();
+    let created = Arc::new(AtomicUsize::new(0));
+    let counter = created.clone();
+    storage.on_create(move |_, _: &mut usize| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    let first = storage.create(100);
+    let spawner = storage.spawner();
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let spawner = spawner.clone();
+            thread::spawn(move || {
+                (0..10)
+                    .map(|i| spawner.spawn(t * 10 + i))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let pointers: Vec<_> = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect();
+    // handles are shared with the regular creation
+    let second = storage.create(200);
+    assert_eq!(created.load(Ordering::Relaxed), 2);
+    assert_eq!(storage.get(&pointers[0]), None);
+    assert_eq!(storage.iter().count(), 2);
+
+    // clones and drops before the commit are accounted for
+    let clone = pointers[5].clone();
+    let dropped = spawner.spawn(1000);
+    drop(dropped.clone());
+    drop(dropped);
+    storage.sync_pending();
+    assert_eq!(created.load(Ordering::Relaxed), 43);
+    assert_eq!(storage.iter().count(), 42);
+    assert_eq!((storage[&first], storage[&second]), (100, 200));
+    for pointer in &pointers {
+        let value = storage[pointer];
+        assert_eq!(pointers[value], *pointer);
+    }
+
+    drop(pointers);
+    storage.sync_pending();
+    assert_eq!(storage.iter().count(), 3);
+    assert_eq!(storage[&clone], 5);
+    // the freed slots are reused by both
+    let third = storage.create(300);
+    let fourth = spawner.spawn(400);
+    storage.sync_pending();
+    assert_eq!((storage[&third], storage[&fourth]), (300, 400));
+    assert_eq!(storage.iter().count(), 5);
+}
+
+#[test]
+fn spawner_uncommitted() {
+    #[derive(Debug, PartialEq)]
+    struct Node(Option<Pointer<Node>>);
+
+    let mut storage = Storage::new();
+    let a = storage.create(Node(None));
+    let b = storage.create(Node(None));
+    // the handle is beyond the ones known to the storage
+    let spawned = storage.spawner().spawn(Node(None));
+    storage[&a].0 = Some(spawned.clone());
+    {
+        let (left, _, right) = storage.split(&b);
+        assert_eq!((left.get(&spawned), right.get(&spawned)), (None, None));
+    }
+    let violations = storage.dependency_violations(|node| node.0.iter().collect());
+    assert!(violations.is_empty());
+    storage.sort_by_dependency(|node| node.0.iter().collect());
+    storage.sync_pending();
+    assert_eq!(storage[&spawned], Node(None));
+}
+
+#[test]
+fn leak_report() {
+    struct Node {
+        _next: Option<Pointer<Node>>,
+    }
+
+    let mut storage = Storage::new();
+    let a = storage.create(Node { _next: None });
+    let b = storage.create(Node {
+        _next: Some(a.clone()),
+    });
+    storage.sync_pending();
+    let c = storage.create(Node { _next: None });
+    let a2 = a.clone();
+    drop(c);
+    let spawned = storage.spawner().spawn(Node { _next: None });
+    let report = storage.into_leak_report();
+    // the pointer from `b` to `a` is not a leak
+    assert_eq!(report.entries(), &[(0, 2), (1, 1), (3, 1)]);
+    assert_eq!(report.outstanding(), 4);
+    assert!(!spawned.is_storage_alive());
+    drop((a, a2, b));
+
+    let mut storage = Storage::new();
+    drop(storage.create(0));
+    assert!(storage.into_leak_report().is_empty());
+}
+
+#[test]
+fn stats() {
+    let mut storage = Storage::new();
+    assert_eq!(storage.stats(), froggy::StorageStats::default());
+    let pointers: Vec<_> = (0..4).map(|i| storage.create(i)).collect();
+    let clones = vec![pointers[1].clone(), pointers[1].clone()];
+    let _spawned = storage.spawner().spawn(4);
+    let stats = storage.stats();
+    assert_eq!(stats.slots, 4);
+    assert_eq!(stats.pending_spawns, 1);
+    if cfg!(not(feature = "atomic-refcount")) {
+        assert_eq!(stats.pending_adds, 3);
+    }
+
+    storage.sync_pending();
+    let stats = storage.stats();
+    assert_eq!((stats.slots, stats.live, stats.max_refcount), (5, 5, 3));
+    assert_eq!((stats.pending_adds, stats.pending_subs), (0, 0));
+    drop((pointers, clones));
+    storage.sync_pending();
+    let stats = storage.stats();
+    assert_eq!((stats.live, stats.free_handles, stats.retired), (1, 4, 0));
+    assert_eq!(stats.fragmentation, 0.8);
+    storage.compact();
+    assert_eq!(storage.stats().fragmentation, 0.0);
+}
+
+#[test]
+fn validate() {
+    use froggy::Violation;
+
+    struct Node {
+        value: usize,
+        _next: Option<Pointer<Node>>,
+    }
+
+    // the collected components are not referenced, but valid
+    let mut collected: Storage<u32> = (0..3).collect();
+    collected.sync_pending();
+    assert_eq!(collected.validate(), Vec::new());
+
+    let mut storage = Storage::new();
+    assert_eq!(storage.validate(), Vec::new());
+    let mut pointers: Vec<Pointer<Node>> = Vec::new();
+    for value in 0..20 {
+        let next = pointers.last().cloned();
+        pointers.push(storage.create(Node { value, _next: next }));
+    }
+    let spawner = storage.spawner();
+    pointers.push(spawner.spawn(Node {
+        value: 20,
+        _next: None,
+    }));
+    match storage.validate()[..] {
+        [Violation::PendingUpdates { spawns: 1, .. }] => (),
+        ref other => panic!("Unexpected violations: {:?}", other),
+    }
+    storage.sync_pending();
+    assert_eq!(storage.validate(), Vec::new());
+
+    // churn: drop every other pointer, take some, and reuse the slots
+    let mut kept = Vec::new();
+    for (i, pointer) in pointers.into_iter().enumerate() {
+        match i % 3 {
+            0 => drop(pointer),
+            1 => kept.push(pointer),
+            _ => {
+                storage.take(&pointer);
+                kept.push(pointer);
+            }
+        }
+        storage.sync_pending();
+        assert_eq!(storage.validate(), Vec::new());
+    }
+    for value in 0..5 {
+        kept.push(storage.create(Node { value, _next: None }));
+        kept.push(spawner.spawn(Node { value, _next: None }));
+    }
+    storage.sync_pending();
+    assert_eq!(storage.validate(), Vec::new());
+    storage.sort_by_key(|node| node.value);
+    assert_eq!(storage.validate(), Vec::new());
+    kept.retain(|pointer| storage.contains(pointer));
+    storage.sync_pending();
+    storage.compact();
+    assert_eq!(storage.validate(), Vec::new());
+    drop(kept);
+    storage.sync_pending();
+    assert_eq!(storage.validate(), Vec::new());
+    assert_eq!(storage.iter_all().count(), 0);
+}